@@ -0,0 +1,304 @@
+pub mod outbox;
+pub mod policy;
+
+use crate::metrics;
+use crate::processing::{FailedChapter, PublishedManga, PublishedMangaChapter};
+use once_cell::sync::Lazy;
+use outbox::Outbox;
+use reqwest::StatusCode;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use rustamanga_mangalib_parser::signature;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const DEFAULT_STREAM_BATCH_SIZE: usize = 1;
+const CALLBACK_SECRET_ENV: &str = "CALLBACK_SECRET";
+/// Whole request including the response body, well below the outbox stale claim age,
+/// so a hung receiver cannot get the same entry redelivered while its attempt is open
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Redirects are not followed, the callback policy checks only `callback_url` itself.
+/// Addresses are checked again when connecting, see [`policy::PublicResolver`]
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .redirect(Policy::none())
+        .dns_resolver(Arc::new(policy::PublicResolver::from_env()))
+        .timeout(REQUEST_TIMEOUT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .expect("HTTP client without redirects is always buildable")
+});
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to serialize payload: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("Request error: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Failed to store undelivered callback: {0}")]
+    Outbox(#[from] outbox::Error),
+    #[error("Receiver redirected with status {0}, redirects are not followed")]
+    Redirect(StatusCode),
+}
+
+#[derive(Debug)]
+pub enum Sent {
+    /// Response body of the receiver
+    Delivered(String),
+    /// Delivery failed, payload is stored in the outbox under this id
+    Queued(String),
+}
+
+/// How scrapped manga is delivered to `callback_url`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Delivery {
+    /// Whole `PublishedManga` in one request after all chapters are scrapped
+    #[default]
+    Single,
+    /// Batches of chapters as soon as they are scrapped, then the `completed` message
+    Stream {
+        #[serde(default = "default_stream_batch_size")]
+        batch_size: usize,
+    },
+}
+
+const fn default_stream_batch_size() -> usize {
+    DEFAULT_STREAM_BATCH_SIZE
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamMessage<'a> {
+    Chapters {
+        slug: &'a str,
+        sequence: u64,
+        chapters: &'a [PublishedMangaChapter],
+    },
+    Completed {
+        slug: &'a str,
+        sequence: u64,
+        chapters_count: usize,
+        failed_chapters: &'a [FailedChapter],
+        missing_chapters: &'a [String],
+    },
+}
+
+/// Signature headers of one delivery attempt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signed {
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+impl Signed {
+    /// Signs the attempt made now
+    pub fn new(secret: &[u8], delivery_id: &str, body: &[u8]) -> Self {
+        let timestamp = signature::now();
+        Self {
+            timestamp,
+            signature: signature::sign(secret, timestamp, delivery_id, body),
+        }
+    }
+}
+
+/// Shared secret of the callback receiver
+#[derive(Clone)]
+pub enum Secret {
+    /// `CALLBACK_SECRET` environment variable
+    Global(String),
+    /// `callback_secret` of the request
+    Request(String),
+}
+
+impl Secret {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Global(secret) | Self::Request(secret) => secret.as_bytes(),
+        }
+    }
+}
+
+/// Callback receiver, requests are signed when the shared secret is set
+#[derive(Clone)]
+pub struct Callback {
+    url: String,
+    secret: Option<Secret>,
+    outbox: Outbox,
+}
+
+impl Callback {
+    /// Secret from the request takes precedence over the global `CALLBACK_SECRET`
+    pub fn new(url: &str, secret: Option<String>) -> Self {
+        Self {
+            url: url.to_string(),
+            secret: match secret {
+                Some(secret) => (!secret.is_empty()).then_some(Secret::Request(secret)),
+                None => global_secret().map(Secret::Global),
+            },
+            outbox: Outbox::from_env(),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Undelivered payload is stored in the outbox, error means it is lost
+    pub async fn send<T: Serialize + ?Sized>(&self, payload: &T) -> Result<Sent, Error> {
+        let body = serde_json::to_string(payload)?;
+        // Redeliveries from the outbox keep this id, so receivers can drop duplicates
+        let delivery_id = Uuid::new_v4().to_string();
+        let signed = self
+            .secret
+            .as_ref()
+            .map(|secret| Signed::new(secret.as_bytes(), &delivery_id, body.as_bytes()));
+        match post(
+            &self.url,
+            &delivery_id,
+            signed.as_ref(),
+            body.clone().into_bytes(),
+        )
+        .await
+        {
+            Ok(response) => Ok(Sent::Delivered(response)),
+            Err(err) => {
+                warn!(
+                    url = self.url,
+                    delivery_id = delivery_id,
+                    "Callback delivery failed: {err:?}"
+                );
+                let id = self
+                    .outbox
+                    .push(
+                        &self.url,
+                        delivery_id,
+                        self.secret.as_ref(),
+                        signed,
+                        body,
+                        &err,
+                    )
+                    .await?;
+
+                Ok(Sent::Queued(id))
+            }
+        }
+    }
+}
+
+pub fn global_secret() -> Option<String> {
+    env::var(CALLBACK_SECRET_ENV)
+        .ok()
+        .filter(|secret| !secret.is_empty())
+}
+
+/// Any non-2xx response is an error, including redirects
+async fn post(
+    url: &str,
+    delivery_id: &str,
+    signed: Option<&Signed>,
+    body: Vec<u8>,
+) -> Result<String, Error> {
+    let mut request = CLIENT
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(signature::DELIVERY_ID_HEADER, delivery_id);
+
+    if let Some(signed) = signed {
+        request = request
+            .header(signature::SIGNATURE_HEADER, &signed.signature)
+            .header(signature::TIMESTAMP_HEADER, signed.timestamp);
+    }
+
+    let response = request.body(body).send().await.inspect_err(|_| {
+        metrics::CALLBACK_DELIVERIES
+            .with_label_values(&["error"])
+            .inc();
+    })?;
+    metrics::CALLBACK_DELIVERIES
+        .with_label_values(&[response.status().as_str()])
+        .inc();
+    if response.status().is_redirection() {
+        return Err(Error::Redirect(response.status()));
+    }
+
+    Ok(response.error_for_status()?.text().await?)
+}
+
+/// Sends chapters from the receiver in batches until it is closed, returns the next sequence number
+pub fn stream_chapters(
+    callback: Callback,
+    slug: String,
+    batch_size: usize,
+    mut receiver: UnboundedReceiver<PublishedMangaChapter>,
+) -> JoinHandle<u64> {
+    tokio::spawn(async move {
+        let batch_size = batch_size.max(1);
+        let mut sequence = 0;
+        let mut batch = Vec::with_capacity(batch_size);
+        while let Some(chapter) = receiver.recv().await {
+            batch.push(chapter);
+            if batch.len() >= batch_size {
+                send_batch(&callback, &slug, sequence, &batch).await;
+                batch.clear();
+                sequence += 1;
+            }
+        }
+
+        if !batch.is_empty() {
+            send_batch(&callback, &slug, sequence, &batch).await;
+            sequence += 1;
+        }
+
+        sequence
+    })
+}
+
+pub async fn send_stream_completed(
+    callback: &Callback,
+    sequence: u64,
+    manga: &PublishedManga,
+) -> Result<Sent, Error> {
+    let message = StreamMessage::Completed {
+        slug: &manga.slug,
+        sequence,
+        chapters_count: manga.chapters.len(),
+        failed_chapters: &manga.failed_chapters,
+        missing_chapters: &manga.missing_chapters,
+    };
+    let sent = callback.send(&message).await?;
+    info!(sent = ?sent, sequence = sequence, "Completion sent");
+
+    Ok(sent)
+}
+
+async fn send_batch(
+    callback: &Callback,
+    slug: &str,
+    sequence: u64,
+    chapters: &[PublishedMangaChapter],
+) {
+    let message = StreamMessage::Chapters {
+        slug,
+        sequence,
+        chapters,
+    };
+    match callback.send(&message).await {
+        Ok(sent) => info!(sent = ?sent, sequence = sequence, "Chapters sent"),
+        Err(err) => error!(
+            manga_slug = slug,
+            sequence = sequence,
+            "Error while sending chapters: {err:?}"
+        ),
+    }
+}
@@ -0,0 +1,605 @@
+use crate::callback::{self, Secret, Signed};
+use crate::config;
+use once_cell::sync::Lazy;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustamanga_mangalib_parser::signature;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio::fs;
+use tracing::{error, info, warn};
+
+const REDELIVERY_INTERVAL: Duration = Duration::from_secs(30);
+const BASE_BACKOFF_SECONDS: u64 = 30;
+const MAX_BACKOFF_SECONDS: u64 = 3600;
+/// Claims older than this are left by a crashed process, their entries are redelivered again
+const STALE_CLAIM: Duration = Duration::from_secs(10 * 60);
+const _: () = assert!(callback::REQUEST_TIMEOUT.as_secs() < STALE_CLAIM.as_secs());
+const ENTRY_EXTENSION: &str = "json";
+const CLAIM_EXTENSION: &str = "sending";
+
+/// Key of request secrets stored in the outbox, 32 bytes in hex
+static ENCRYPTION_KEY: Lazy<Option<Arc<LessSafeKey>>> = Lazy::new(|| {
+    let value = env::var("OUTBOX_ENCRYPTION_KEY")
+        .ok()
+        .filter(|value| !value.trim().is_empty())?;
+    let key = hex::decode(value.trim())
+        .ok()
+        .and_then(|key| UnboundKey::new(&AES_256_GCM, &key).ok());
+    if key.is_none() {
+        warn!("OUTBOX_ENCRYPTION_KEY is not 32 bytes in hex, request secrets are not stored");
+    }
+
+    key.map(|key| Arc::new(LessSafeKey::new(key)))
+});
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Outbox IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse outbox entry: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("Outbox entry {0} not found")]
+    NotFound(String),
+    #[error("CALLBACK_SECRET is not set")]
+    MissingSecret,
+    #[error("Failed to encrypt or decrypt request secret")]
+    Crypto,
+}
+
+/// How redeliveries of the entry are signed, each attempt with its own timestamp
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Signer {
+    /// With `CALLBACK_SECRET` at the time of redelivery
+    Global,
+    /// With the request secret, encrypted by `OUTBOX_ENCRYPTION_KEY` with the entry id
+    /// as associated data, hex encoded
+    Encrypted { nonce: String, secret: String },
+    /// Request secret without `OUTBOX_ENCRYPTION_KEY`, headers of the first attempt are repeated
+    Replayed(Signed),
+}
+
+/// Undelivered callback request stored as `{id}.json` file, renamed to `{id}.sending` while it is redelivered
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Also the `X-Delivery-Id` of every attempt
+    pub id: String,
+    pub url: String,
+    /// `None` for unsigned callbacks
+    pub signer: Option<Signer>,
+    pub body: String,
+    pub attempts: u32,
+    pub created_at: u64,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+    /// Entries out of attempts are kept, but redelivered only on demand
+    #[serde(default)]
+    pub dead: bool,
+}
+
+impl Entry {
+    fn new(
+        url: &str,
+        id: String,
+        signer: Option<Signer>,
+        body: String,
+        error: &callback::Error,
+    ) -> Self {
+        let now = signature::now();
+        Self {
+            id,
+            url: url.to_string(),
+            signer,
+            body,
+            attempts: 1,
+            created_at: now,
+            next_attempt_at: now + backoff(1),
+            last_error: Some(error.to_string()),
+            dead: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    directory: PathBuf,
+    max_attempts: u32,
+    key: Option<Arc<LessSafeKey>>,
+}
+
+impl Outbox {
+    pub fn from_env() -> Self {
+        Self {
+            key: ENCRYPTION_KEY.clone(),
+            ..Self::new(
+                env::var("OUTBOX_DIRECTORY_PATH")
+                    .unwrap_or_else(|_| config::DEFAULT_OUTBOX_DIRECTORY_PATH.to_string()),
+                env::var("OUTBOX_MAX_ATTEMPTS")
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(config::DEFAULT_OUTBOX_MAX_ATTEMPTS),
+            )
+        }
+    }
+
+    pub fn new<T: Into<PathBuf>>(directory: T, max_attempts: u32) -> Self {
+        Self {
+            directory: directory.into(),
+            max_attempts: max_attempts.max(1),
+            key: None,
+        }
+    }
+
+    /// `signed` are the headers of the first attempt, made with `secret`
+    pub async fn push(
+        &self,
+        url: &str,
+        id: String,
+        secret: Option<&Secret>,
+        signed: Option<Signed>,
+        body: String,
+        error: &callback::Error,
+    ) -> Result<String, Error> {
+        let signer = match (secret, signed) {
+            (Some(Secret::Global(_)), _) => Some(Signer::Global),
+            (Some(Secret::Request(secret)), Some(signed)) => Some(match &self.key {
+                Some(key) => seal(key, &id, secret)?,
+                None => {
+                    warn!(
+                        delivery_id = id,
+                        "OUTBOX_ENCRYPTION_KEY is not set, redeliveries repeat the first signature"
+                    );
+                    Signer::Replayed(signed)
+                }
+            }),
+            _ => None,
+        };
+        let entry = Entry::new(url, id, signer, body, error);
+        self.save(&entry).await?;
+
+        Ok(entry.id)
+    }
+
+    /// Corrupt entries are logged and skipped, claimed ones are not listed
+    pub async fn list(&self) -> Result<Vec<Entry>, Error> {
+        let mut entries = vec![];
+        let mut dir = match fs::read_dir(&self.directory).await {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(entries),
+            Err(err) => return Err(err.into()),
+        };
+
+        while let Some(file) = dir.next_entry().await? {
+            let path = file.path();
+            if !has_extension(&path, ENTRY_EXTENSION) {
+                continue;
+            }
+            match read(&path).await {
+                Ok(entry) => entries.push(entry),
+                // Entry may be claimed and delivered since the directory was read
+                Err(Error::Io(err)) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => warn!(path = ?path, "Skipping corrupt outbox entry: {err:?}"),
+            }
+        }
+        entries.sort_by_key(|entry: &Entry| entry.created_at);
+
+        Ok(entries)
+    }
+
+    /// Redelivers live entries whose backoff has passed, or all of them, dead ones too, when `force` is set
+    pub async fn redeliver_due(&self, force: bool) -> Result<usize, Error> {
+        self.release_stale_claims().await?;
+
+        let now = signature::now();
+        let mut delivered = 0;
+        for entry in self.list().await? {
+            let is_due = force || (!entry.dead && entry.next_attempt_at <= now);
+            if !is_due {
+                continue;
+            }
+            match self.redeliver_entry(&entry.id).await {
+                Ok(true) => delivered += 1,
+                Ok(false) => {}
+                Err(err) => error!(
+                    delivery_id = entry.id,
+                    "Failed to redeliver callback: {err:?}"
+                ),
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    pub async fn redeliver(&self, id: &str) -> Result<bool, Error> {
+        let entry = self
+            .list()
+            .await?
+            .into_iter()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| Error::NotFound(id.to_string()))?;
+
+        self.redeliver_entry(&entry.id).await
+    }
+
+    pub fn spawn_redelivery(self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REDELIVERY_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.redeliver_due(false).await {
+                    error!("Failed to redeliver callbacks: {err:?}");
+                }
+            }
+        });
+    }
+
+    /// Entry is claimed by renaming, so processes sharing the outbox do not deliver it twice at once
+    async fn redeliver_entry(&self, id: &str) -> Result<bool, Error> {
+        let claim = self.path(id, CLAIM_EXTENSION);
+        match fs::rename(self.path(id, ENTRY_EXTENSION), &claim).await {
+            Ok(()) => {}
+            // Claimed by another process, or already delivered
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        }
+        // Renaming keeps modification time of the entry, claim age starts now
+        touch(&claim).await?;
+        let mut entry = match read(&claim).await {
+            Ok(entry) => entry,
+            Err(err) => {
+                fs::rename(&claim, self.path(id, ENTRY_EXTENSION)).await?;
+                return Err(err);
+            }
+        };
+
+        let delivered = match self.sign(&entry) {
+            Ok(signed) => callback::post(
+                &entry.url,
+                &entry.id,
+                signed.as_ref(),
+                entry.body.clone().into_bytes(),
+            )
+            .await
+            .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        match delivered {
+            Ok(_) => {
+                info!(
+                    delivery_id = entry.id,
+                    url = entry.url,
+                    attempts = entry.attempts,
+                    "Callback redelivered"
+                );
+                remove(&claim).await?;
+
+                Ok(true)
+            }
+            Err(err) => {
+                entry.attempts += 1;
+                entry.next_attempt_at = signature::now() + backoff(entry.attempts);
+                entry.last_error = Some(err.clone());
+                entry.dead = entry.dead || entry.attempts >= self.max_attempts;
+                warn!(
+                    delivery_id = entry.id,
+                    url = entry.url,
+                    attempts = entry.attempts,
+                    dead = entry.dead,
+                    "Callback redelivery failed: {err}"
+                );
+                self.save(&entry).await?;
+                remove(&claim).await?;
+
+                Ok(false)
+            }
+        }
+    }
+
+    /// Headers of the attempt made now
+    fn sign(&self, entry: &Entry) -> Result<Option<Signed>, Error> {
+        let body = entry.body.as_bytes();
+        match &entry.signer {
+            None => Ok(None),
+            Some(Signer::Global) => {
+                let secret = callback::global_secret().ok_or(Error::MissingSecret)?;
+                Ok(Some(Signed::new(secret.as_bytes(), &entry.id, body)))
+            }
+            Some(Signer::Encrypted { nonce, secret }) => {
+                let key = self.key.as_ref().ok_or(Error::Crypto)?;
+                let secret = open(key, &entry.id, nonce, secret)?;
+                Ok(Some(Signed::new(&secret, &entry.id, body)))
+            }
+            Some(Signer::Replayed(signed)) => Ok(Some(signed.clone())),
+        }
+    }
+
+    async fn release_stale_claims(&self) -> Result<(), Error> {
+        let mut dir = match fs::read_dir(&self.directory).await {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        while let Some(file) = dir.next_entry().await? {
+            let path = file.path();
+            let is_stale = file
+                .metadata()
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age > STALE_CLAIM);
+            if has_extension(&path, CLAIM_EXTENSION) && is_stale {
+                warn!(path = ?path, "Releasing stale outbox claim");
+                match fs::rename(&path, path.with_extension(ENTRY_EXTENSION)).await {
+                    Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn save(&self, entry: &Entry) -> Result<(), Error> {
+        fs::create_dir_all(&self.directory).await?;
+        let path = self.path(&entry.id, ENTRY_EXTENSION);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(entry)?).await?;
+        fs::rename(tmp_path, path).await?;
+
+        Ok(())
+    }
+
+    fn path(&self, id: &str, extension: &str) -> PathBuf {
+        self.directory.join(format!("{id}.{extension}"))
+    }
+}
+
+fn seal(key: &LessSafeKey, id: &str, secret: &str) -> Result<Signer, Error> {
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| Error::Crypto)?;
+    let mut sealed = secret.as_bytes().to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(id.as_bytes()),
+        &mut sealed,
+    )
+    .map_err(|_| Error::Crypto)?;
+
+    Ok(Signer::Encrypted {
+        nonce: hex::encode(nonce),
+        secret: hex::encode(sealed),
+    })
+}
+
+fn open(key: &LessSafeKey, id: &str, nonce: &str, secret: &str) -> Result<Vec<u8>, Error> {
+    let nonce = hex::decode(nonce)
+        .ok()
+        .and_then(|nonce| Nonce::try_assume_unique_for_key(&nonce).ok())
+        .ok_or(Error::Crypto)?;
+    let mut sealed = hex::decode(secret).map_err(|_| Error::Crypto)?;
+    let secret = key
+        .open_in_place(nonce, Aad::from(id.as_bytes()), &mut sealed)
+        .map_err(|_| Error::Crypto)?;
+
+    Ok(secret.to_vec())
+}
+
+async fn read(path: &Path) -> Result<Entry, Error> {
+    Ok(serde_json::from_slice(&fs::read(path).await?)?)
+}
+
+async fn touch(path: &Path) -> Result<(), Error> {
+    let file = fs::OpenOptions::new().write(true).open(path).await?;
+    file.into_std().await.set_modified(SystemTime::now())?;
+
+    Ok(())
+}
+
+/// Missing file was already removed by another process
+async fn remove(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().is_some_and(|value| value == extension)
+}
+
+fn backoff(attempts: u32) -> u64 {
+    BASE_BACKOFF_SECONDS
+        .saturating_mul(2_u64.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_BACKOFF_SECONDS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &[7; 32]).unwrap())
+    }
+
+    fn outbox(max_attempts: u32) -> Outbox {
+        Outbox::new(
+            env::temp_dir().join(format!("outbox-test-{}", uuid::Uuid::new_v4())),
+            max_attempts,
+        )
+    }
+
+    /// Port 1 refuses connections, so every delivery fails
+    fn entry(id: &str, created_at: u64) -> Entry {
+        Entry {
+            id: id.to_string(),
+            url: "http://127.0.0.1:1/callback".to_string(),
+            signer: None,
+            body: "{}".to_string(),
+            attempts: 1,
+            created_at,
+            next_attempt_at: 0,
+            last_error: None,
+            dead: false,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_limit() {
+        assert_eq!(backoff(1), 30);
+        assert_eq!(backoff(2), 60);
+        assert_eq!(backoff(3), 120);
+        assert_eq!(backoff(8), MAX_BACKOFF_SECONDS);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF_SECONDS);
+    }
+
+    #[tokio::test]
+    async fn lists_entries_by_creation_skipping_corrupt_and_claimed() {
+        let outbox = outbox(3);
+        outbox.save(&entry("second", 2)).await.unwrap();
+        outbox.save(&entry("first", 1)).await.unwrap();
+        outbox.save(&entry("claimed", 0)).await.unwrap();
+        fs::rename(
+            outbox.path("claimed", ENTRY_EXTENSION),
+            outbox.path("claimed", CLAIM_EXTENSION),
+        )
+        .await
+        .unwrap();
+        fs::write(outbox.path("corrupt", ENTRY_EXTENSION), "{")
+            .await
+            .unwrap();
+
+        let ids: Vec<String> = outbox
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert_eq!(ids, ["first", "second"]);
+
+        fs::remove_dir_all(&outbox.directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claimed_entry_is_not_redelivered() {
+        let outbox = outbox(3);
+        outbox.save(&entry("claimed", 0)).await.unwrap();
+        fs::rename(
+            outbox.path("claimed", ENTRY_EXTENSION),
+            outbox.path("claimed", CLAIM_EXTENSION),
+        )
+        .await
+        .unwrap();
+
+        assert!(!outbox.redeliver_entry("claimed").await.unwrap());
+        assert!(matches!(
+            outbox.redeliver("claimed").await,
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            outbox.redeliver("unknown").await,
+            Err(Error::NotFound(_))
+        ));
+
+        fs::remove_dir_all(&outbox.directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_stale_claims_are_released() {
+        let outbox = outbox(3);
+        for id in ["stale", "fresh"] {
+            outbox.save(&entry(id, 0)).await.unwrap();
+            fs::rename(
+                outbox.path(id, ENTRY_EXTENSION),
+                outbox.path(id, CLAIM_EXTENSION),
+            )
+            .await
+            .unwrap();
+        }
+        std::fs::File::options()
+            .write(true)
+            .open(outbox.path("stale", CLAIM_EXTENSION))
+            .unwrap()
+            .set_modified(SystemTime::now() - STALE_CLAIM * 2)
+            .unwrap();
+
+        outbox.release_stale_claims().await.unwrap();
+        let ids: Vec<String> = outbox
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert_eq!(ids, ["stale"]);
+        assert!(outbox.path("fresh", CLAIM_EXTENSION).exists());
+
+        fs::remove_dir_all(&outbox.directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_entry_backs_off_and_dies_after_max_attempts() {
+        let outbox = outbox(3);
+        outbox.save(&entry("entry", 0)).await.unwrap();
+
+        assert_eq!(outbox.redeliver_due(false).await.unwrap(), 0);
+        let [entry] = outbox.list().await.unwrap().try_into().unwrap();
+        assert_eq!(entry.attempts, 2);
+        assert!(!entry.dead);
+        assert!(entry.last_error.is_some());
+        assert!(entry.next_attempt_at > signature::now());
+        assert!(!outbox.path("entry", CLAIM_EXTENSION).exists());
+
+        // Backoff has not passed yet
+        outbox.redeliver_due(false).await.unwrap();
+        let [entry] = outbox.list().await.unwrap().try_into().unwrap();
+        assert_eq!(entry.attempts, 2);
+
+        outbox.redeliver("entry").await.unwrap();
+        let [entry] = outbox.list().await.unwrap().try_into().unwrap();
+        assert_eq!(entry.attempts, 3);
+        assert!(entry.dead);
+
+        // Dead entries are redelivered only when forced
+        let mut dead = entry.clone();
+        dead.next_attempt_at = 0;
+        outbox.save(&dead).await.unwrap();
+        outbox.redeliver_due(false).await.unwrap();
+        let [entry] = outbox.list().await.unwrap().try_into().unwrap();
+        assert_eq!(entry.attempts, 3);
+        outbox.redeliver_due(true).await.unwrap();
+        let [entry] = outbox.list().await.unwrap().try_into().unwrap();
+        assert_eq!(entry.attempts, 4);
+        assert!(entry.dead);
+
+        fs::remove_dir_all(&outbox.directory).await.unwrap();
+    }
+
+    #[test]
+    fn request_secret_is_encrypted_for_its_entry() {
+        let Signer::Encrypted { nonce, secret } = seal(&key(), "entry", "topsecret").unwrap()
+        else {
+            panic!("secret is not encrypted");
+        };
+
+        assert!(!secret.contains(&hex::encode("topsecret")));
+        assert_eq!(
+            open(&key(), "entry", &nonce, &secret).unwrap(),
+            b"topsecret"
+        );
+        assert!(matches!(
+            open(&key(), "other", &nonce, &secret),
+            Err(Error::Crypto)
+        ));
+    }
+}
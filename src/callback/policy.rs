@@ -0,0 +1,305 @@
+use reqwest::Url;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use thiserror::Error;
+use tokio::net::lookup_host;
+
+const DEFAULT_ALLOWED_SCHEMES: &str = "http,https";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("url is invalid: {0}")]
+    Parse(String),
+    #[error("scheme {0} is not allowed")]
+    Scheme(String),
+    #[error("host is missing")]
+    MissingHost,
+    #[error("host {0} is not allowed")]
+    Host(String),
+    #[error("failed to resolve host {host}: {reason}")]
+    Resolve { host: String, reason: String },
+    #[error("host {host} resolves to non-public address {address}")]
+    Address { host: String, address: IpAddr },
+}
+
+/// Restricts where callbacks may be sent, so requests cannot make the parser reach internal services
+#[derive(Debug, Clone)]
+pub struct CallbackPolicy {
+    schemes: Vec<String>,
+    allowed_hosts: Vec<String>,
+    denied_hosts: Vec<String>,
+    allow_private: bool,
+}
+
+impl CallbackPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            schemes: list(
+                &env::var("CALLBACK_ALLOWED_SCHEMES")
+                    .unwrap_or_else(|_| DEFAULT_ALLOWED_SCHEMES.to_string()),
+            ),
+            allowed_hosts: list(&env::var("CALLBACK_ALLOWED_HOSTS").unwrap_or_default()),
+            denied_hosts: list(&env::var("CALLBACK_DENIED_HOSTS").unwrap_or_default()),
+            allow_private: allow_private_from_env(),
+        }
+    }
+
+    /// Host lists match the host itself and its subdomains, empty allowlist allows any host.
+    /// Addresses are checked here to reject a request early, [`PublicResolver`] enforces them
+    pub async fn check(&self, url: &str) -> Result<(), Error> {
+        let url = Url::parse(url).map_err(|err| Error::Parse(err.to_string()))?;
+        if !self.schemes.iter().any(|scheme| scheme == url.scheme()) {
+            return Err(Error::Scheme(url.scheme().to_string()));
+        }
+
+        let host = url
+            .host_str()
+            .filter(|host| !host.is_empty())
+            .ok_or(Error::MissingHost)?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_lowercase();
+        if self
+            .denied_hosts
+            .iter()
+            .any(|denied| matches_host(&host, denied))
+            || (!self.allowed_hosts.is_empty()
+                && !self
+                    .allowed_hosts
+                    .iter()
+                    .any(|allowed| matches_host(&host, allowed)))
+        {
+            return Err(Error::Host(host));
+        }
+
+        if self.allow_private {
+            return Ok(());
+        }
+
+        let port = url.port_or_known_default().unwrap_or(80);
+        resolve_public(&host, port).await.map(|_| ())
+    }
+}
+
+/// DNS resolver of the callback HTTP client. Callback hosts are resolved again on every connection,
+/// including outbox redeliveries, so a host cannot pass [`CallbackPolicy::check`] and then resolve
+/// to an internal address
+#[derive(Debug, Clone, Copy)]
+pub struct PublicResolver {
+    allow_private: bool,
+}
+
+impl PublicResolver {
+    pub fn from_env() -> Self {
+        Self {
+            allow_private: allow_private_from_env(),
+        }
+    }
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allow_private = self.allow_private;
+        Box::pin(async move {
+            // Port is replaced by the port of the URL
+            let addresses = if allow_private {
+                lookup_host((name.as_str(), 0)).await?.collect()
+            } else {
+                resolve_public(name.as_str(), 0).await?
+            };
+            let addresses: Addrs = Box::new(addresses.into_iter());
+
+            Ok(addresses)
+        })
+    }
+}
+
+fn allow_private_from_env() -> bool {
+    env::var("CALLBACK_ALLOW_PRIVATE_ADDRESSES").is_ok_and(|value| value == "true")
+}
+
+/// Host is rejected when any of its addresses is not public
+async fn resolve_public(host: &str, port: u16) -> Result<Vec<SocketAddr>, Error> {
+    let addresses: Vec<SocketAddr> = lookup_host((host, port))
+        .await
+        .map_err(|err| Error::Resolve {
+            host: host.to_string(),
+            reason: err.to_string(),
+        })?
+        .collect();
+
+    match addresses.iter().find(|address| !is_public(address.ip())) {
+        Some(address) => Err(Error::Address {
+            host: host.to_string(),
+            address: address.ip(),
+        }),
+        None => Ok(addresses),
+    }
+}
+
+pub fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Pattern matches the host itself and its subdomains
+pub fn matches_host(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim_start_matches("*.").trim_start_matches('.');
+
+    host == pattern
+        || host
+            .strip_suffix(pattern)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_public_v4(address),
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(address) => is_public_v4(address),
+            None => is_public_v6(address),
+        },
+    }
+}
+
+/// Loopback, private, link-local (including cloud metadata `169.254.169.254`), CGNAT and reserved ranges are not public
+fn is_public_v4(address: Ipv4Addr) -> bool {
+    let [first, second, third, _] = address.octets();
+
+    !(address.is_unspecified()
+        || address.is_loopback()
+        || address.is_private()
+        || address.is_link_local()
+        || address.is_broadcast()
+        || address.is_documentation()
+        || address.is_multicast()
+        || first == 0
+        || first >= 240
+        || (first == 100 && (64..128).contains(&second))
+        || (first == 198 && (18..20).contains(&second))
+        || (first == 192 && second == 0 && third == 0))
+}
+
+fn is_public_v6(address: Ipv6Addr) -> bool {
+    let first = address.segments()[0];
+
+    !(address.is_unspecified()
+        || address.is_loopback()
+        || address.is_multicast()
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first == 0x2001 && address.segments()[1] == 0x0db8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allowed_hosts: &str, denied_hosts: &str, allow_private: bool) -> CallbackPolicy {
+        CallbackPolicy {
+            schemes: list(DEFAULT_ALLOWED_SCHEMES),
+            allowed_hosts: list(allowed_hosts),
+            denied_hosts: list(denied_hosts),
+            allow_private,
+        }
+    }
+
+    #[test]
+    fn matches_host_and_subdomains() {
+        assert!(matches_host("example.com", "example.com"));
+        assert!(matches_host("api.example.com", "example.com"));
+        assert!(matches_host("api.example.com", "*.example.com"));
+        assert!(matches_host("api.example.com", ".example.com"));
+        assert!(!matches_host("badexample.com", "example.com"));
+        assert!(!matches_host("example.com.evil.net", "example.com"));
+    }
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for address in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "198.18.0.1",
+            "192.0.0.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "2001:db8::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(address.parse().unwrap()), "{address}");
+        }
+    }
+
+    #[test]
+    fn external_addresses_are_public() {
+        for address in [
+            "8.8.8.8",
+            "100.128.0.1",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+        ] {
+            assert!(is_public(address.parse().unwrap()), "{address}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_disallowed_scheme_and_hosts() {
+        let policy = policy("example.com", "internal.example.com", true);
+
+        assert!(policy.check("https://api.example.com/hook").await.is_ok());
+        assert!(matches!(
+            policy.check("ftp://example.com/hook").await,
+            Err(Error::Scheme(_))
+        ));
+        assert!(matches!(
+            policy.check("https://internal.example.com/hook").await,
+            Err(Error::Host(_))
+        ));
+        assert!(matches!(
+            policy.check("https://example.org/hook").await,
+            Err(Error::Host(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_private_address_unless_allowed() {
+        assert!(matches!(
+            policy("", "", false)
+                .check("http://127.0.0.1:8080/hook")
+                .await,
+            Err(Error::Address { .. })
+        ));
+        assert!(matches!(
+            policy("", "", false).check("http://[::1]/hook").await,
+            Err(Error::Address { .. })
+        ));
+        assert!(
+            policy("", "", true)
+                .check("http://127.0.0.1:8080/hook")
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn resolver_rejects_private_address_unless_allowed() {
+        let resolve =
+            |allow_private| PublicResolver { allow_private }.resolve("localhost".parse().unwrap());
+
+        assert!(resolve(false).await.is_err());
+        assert!(resolve(true).await.unwrap().next().is_some());
+    }
+}
@@ -1,7 +1,10 @@
+use crate::callback::outbox::{self, Outbox};
 use crate::mangalib::MangaPreview;
 use crate::{config, mangalib, rabbitmq_consumer, send_resource, server};
 use clap::{ArgMatches, Command, arg};
 use futures::StreamExt;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::time::Duration;
 use thiserror::Error;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
@@ -18,7 +21,12 @@ fn get_settings() -> Command {
             Command::new("serve")
                 .about("Start web server")
                 .arg(arg!(--port <PORT> "Web server port"))
-                .arg(arg!(--browsers <BROWSERS> "Max chrome browsers count")),
+                .arg(arg!(--browsers <BROWSERS> "Max chrome browsers count"))
+                .arg(arg!(--"sync-timeout" <SECONDS> "Max duration of synchronous scrapping"))
+                .arg(arg!(--"grace-period" <SECONDS> "Max wait for running jobs on shutdown"))
+                .arg(arg!(--"queue-size" <SIZE> "Max count of jobs waiting for a worker"))
+                .arg(arg!(--workers <WORKERS> "Count of jobs scrapped at once"))
+                .arg(arg!(--"max-deadline" <SECONDS> "Max duration of a job")),
             Command::new("send-resource")
                 .about("Send start static resource")
                 .arg(arg!(--url <URL> "URL where we should send this resource"))
@@ -28,7 +36,19 @@ fn get_settings() -> Command {
                 .about("Consume RabbitMQ queue")
                 .arg(arg!(--url <URL> "AMQP URI"))
                 .arg(arg!(--browsers <BROWSERS> "Max chrome browsers count"))
+                .arg(arg!(--"health-port" <PORT> "Port of health and metrics endpoints"))
+                .arg(arg!(--"grace-period" <SECONDS> "Max wait for running job on shutdown"))
+                .arg(arg!(--"max-deadline" <SECONDS> "Max duration of a job"))
                 .arg_required_else_help(true),
+            Command::new("outbox")
+                .about("Manage undelivered callbacks")
+                .subcommand_required(true)
+                .subcommands([
+                    Command::new("list").about("List pending callbacks"),
+                    Command::new("redeliver")
+                        .about("Redeliver pending callbacks now")
+                        .arg(arg!(--id <ID> "Redeliver only this callback")),
+                ]),
         ])
 }
 
@@ -46,6 +66,8 @@ pub enum Error {
     Consume(#[from] rabbitmq_consumer::Error),
     #[error("Failed to parse arguments: {0}")]
     BadArgument(String),
+    #[error("Outbox error: {0}")]
+    Outbox(#[from] outbox::Error),
 }
 
 pub async fn process_commands() -> Result<(), Error> {
@@ -53,8 +75,22 @@ pub async fn process_commands() -> Result<(), Error> {
         Some(("serve", sub_matches)) => {
             let port = parse_port(sub_matches)?;
             let chrome_max_count = parse_chrome_max_count(sub_matches)?;
+            let sync_timeout = parse_sync_timeout(sub_matches)?;
+            let grace_period = parse_grace_period(sub_matches)?;
+            let queue_size = parse_count(sub_matches, "queue-size", config::DEFAULT_QUEUE_SIZE)?;
+            let workers_count = parse_count(sub_matches, "workers", config::DEFAULT_WORKERS_COUNT)?;
+            let max_deadline = parse_max_deadline(sub_matches)?;
 
-            serve(port, chrome_max_count).await
+            serve(
+                port,
+                chrome_max_count,
+                sync_timeout,
+                grace_period,
+                queue_size,
+                workers_count,
+                max_deadline,
+            )
+            .await
         }
         Some(("send-resource", sub_matches)) => {
             let url = sub_matches.get_one::<String>("url").expect("required");
@@ -63,8 +99,18 @@ pub async fn process_commands() -> Result<(), Error> {
         Some(("consume", sub_matches)) => {
             let url = sub_matches.get_one::<String>("url").expect("required");
             let chrome_max_count = parse_chrome_max_count(sub_matches)?;
+            let health_port = parse_health_port(sub_matches)?;
+            let grace_period = parse_grace_period(sub_matches)?;
+            let max_deadline = parse_max_deadline(sub_matches)?;
 
-            consume(url, chrome_max_count).await
+            consume(
+                url,
+                chrome_max_count,
+                health_port,
+                grace_period,
+                max_deadline,
+            )
+            .await
         }
         Some(("collect-resource-full", _sub_matches)) => {
             let iter = mangalib::search::get_manga_iter();
@@ -82,6 +128,14 @@ pub async fn process_commands() -> Result<(), Error> {
 
             Ok(())
         }
+        Some(("outbox", sub_matches)) => match sub_matches.subcommand() {
+            Some(("list", _)) => outbox_list().await,
+            Some(("redeliver", sub_matches)) => {
+                outbox_redeliver(sub_matches.get_one::<String>("id")).await
+            }
+            Some((command, _)) => Err(Error::NoSuchCommand(command.to_string())),
+            None => Err(Error::NoCommandSpecified),
+        },
         Some((command, _)) => Err(Error::NoSuchCommand(command.to_string())),
         None => Err(Error::NoCommandSpecified),
     }
@@ -103,14 +157,122 @@ fn parse_port(sub_matches: &ArgMatches) -> Result<u16, Error> {
         .map_err(|err| Error::BadArgument(format!("Failed to parse port: {err}")))
 }
 
-async fn serve(port: u16, chrome_max_count: u16) -> Result<(), Error> {
-    Ok(server::serve(port, chrome_max_count).await?)
+fn parse_health_port(sub_matches: &ArgMatches) -> Result<Option<u16>, Error> {
+    sub_matches
+        .get_one::<String>("health-port")
+        .map(|port| port.parse::<u16>())
+        .transpose()
+        .map_err(|err| Error::BadArgument(format!("Failed to parse health port: {err}")))
+}
+
+fn parse_sync_timeout(sub_matches: &ArgMatches) -> Result<Duration, Error> {
+    sub_matches
+        .get_one::<String>("sync-timeout")
+        .unwrap_or(&config::DEFAULT_SYNC_TIMEOUT_SECONDS.to_string())
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|err| Error::BadArgument(format!("Failed to parse sync timeout: {err}")))
+}
+
+fn parse_grace_period(sub_matches: &ArgMatches) -> Result<Duration, Error> {
+    sub_matches
+        .get_one::<String>("grace-period")
+        .unwrap_or(&config::DEFAULT_SHUTDOWN_GRACE_PERIOD_SECONDS.to_string())
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|err| Error::BadArgument(format!("Failed to parse grace period: {err}")))
+}
+
+/// Zero max deadline would time out every job at once
+fn parse_max_deadline(sub_matches: &ArgMatches) -> Result<Duration, Error> {
+    sub_matches
+        .get_one::<String>("max-deadline")
+        .unwrap_or(&config::DEFAULT_JOB_MAX_DEADLINE_SECONDS.to_string())
+        .parse::<NonZeroU64>()
+        .map(|seconds| Duration::from_secs(seconds.get()))
+        .map_err(|err| Error::BadArgument(format!("Failed to parse max deadline: {err}")))
+}
+
+/// Zero queue size or workers count would leave the server unable to run jobs
+fn parse_count(sub_matches: &ArgMatches, name: &str, default: usize) -> Result<usize, Error> {
+    sub_matches
+        .get_one::<String>(name)
+        .map_or(Ok(default), |count| {
+            count.parse::<NonZeroUsize>().map(NonZeroUsize::get)
+        })
+        .map_err(|err| Error::BadArgument(format!("Failed to parse {name}: {err}")))
+}
+
+async fn serve(
+    port: u16,
+    chrome_max_count: u16,
+    sync_timeout: Duration,
+    grace_period: Duration,
+    queue_size: usize,
+    workers_count: usize,
+    max_deadline: Duration,
+) -> Result<(), Error> {
+    Ok(server::serve(
+        port,
+        chrome_max_count,
+        sync_timeout,
+        grace_period,
+        queue_size,
+        workers_count,
+        max_deadline,
+    )
+    .await?)
 }
 
 async fn send_resource(url: &str) -> Result<(), Error> {
     Ok(send_resource::send_resource(url).await?)
 }
 
-async fn consume(url: &str, chrome_max_count: u16) -> Result<(), Error> {
-    Ok(rabbitmq_consumer::consume(url, chrome_max_count).await?)
+async fn consume(
+    url: &str,
+    chrome_max_count: u16,
+    health_port: Option<u16>,
+    grace_period: Duration,
+    max_deadline: Duration,
+) -> Result<(), Error> {
+    Ok(rabbitmq_consumer::consume(
+        url,
+        chrome_max_count,
+        health_port,
+        grace_period,
+        max_deadline,
+    )
+    .await?)
+}
+
+async fn outbox_list() -> Result<(), Error> {
+    for entry in Outbox::from_env().list().await? {
+        println!(
+            "{} url={} attempts={} next_attempt_at={} dead={} last_error={}",
+            entry.id,
+            entry.url,
+            entry.attempts,
+            entry.next_attempt_at,
+            entry.dead,
+            entry.last_error.unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+async fn outbox_redeliver(id: Option<&String>) -> Result<(), Error> {
+    let outbox = Outbox::from_env();
+    match id {
+        Some(id) => {
+            let delivered = outbox.redeliver(id).await?;
+            println!("{id} delivered={delivered}");
+        }
+        None => {
+            let delivered = outbox.redeliver_due(true).await?;
+            println!("Delivered {delivered} callbacks");
+        }
+    }
+
+    Ok(())
 }
@@ -11,7 +11,18 @@ use tracing_subscriber::{
 
 pub const DEFAULT_APP_PORT: u16 = 8000;
 pub const DEFAULT_CHROME_MAX_COUNT: u16 = 16;
+pub const DEFAULT_SYNC_TIMEOUT_SECONDS: u64 = 300;
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD_SECONDS: u64 = 60;
+pub const DEFAULT_QUEUE_SIZE: usize = 100;
+pub const DEFAULT_WORKERS_COUNT: usize = 4;
+pub const DEFAULT_IDEMPOTENCY_WINDOW_SECONDS: u64 = 24 * 60 * 60;
+pub const DEFAULT_JOB_MAX_DEADLINE_SECONDS: u64 = 60 * 60;
 pub const DEFAULT_LOG_DIRECTORY_PATH: &str = "/var/log/rustamanga-mangalib-parser";
+pub const DEFAULT_OUTBOX_DIRECTORY_PATH: &str = "/var/lib/rustamanga-mangalib-parser/outbox";
+pub const DEFAULT_OUTBOX_MAX_ATTEMPTS: u32 = 10;
+pub const DEFAULT_JOB_STORE_PATH: &str = "/var/lib/rustamanga-mangalib-parser/jobs.sqlite";
+pub const DEFAULT_IMAGE_CACHE_DIRECTORY_PATH: &str = "/var/cache/rustamanga-mangalib-parser/images";
+pub const DEFAULT_IMAGE_CACHE_MAX_BYTES: u64 = 1024 * 1024 * 1024;
 
 static GUARD: OnceCell<WorkerGuard> = OnceCell::new();
 
@@ -0,0 +1,521 @@
+pub mod cancellation;
+pub mod events;
+mod memory;
+mod sqlite;
+
+use crate::config;
+use cancellation::{Cancellation, Reason};
+use events::JobEvent;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+pub use memory::MemoryJobStore;
+pub use sqlite::SqliteJobStore;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Job store IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to (de)serialize job data: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("Can't get job store lock")]
+    Lock,
+    #[error("Job {0} not found")]
+    NotFound(String),
+    #[error("Bad job status {0}")]
+    BadStatus(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    /// Failed and cancelled jobs are redone for a repeated idempotency key, e.g. requeued AMQP message
+    pub const fn is_reusable(self) -> bool {
+        matches!(self, Self::Queued | Self::Running | Self::Succeeded)
+    }
+}
+
+impl std::str::FromStr for JobStatus {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "queued" => Ok(Self::Queued),
+            "running" => Ok(Self::Running),
+            "succeeded" => Ok(Self::Succeeded),
+            "failed" => Ok(Self::Failed),
+            "cancelled" => Ok(Self::Cancelled),
+            _ => Err(Error::BadStatus(value.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Job {
+    pub id: String,
+    pub slug: String,
+    #[schema(value_type = Object)]
+    pub request: Value,
+    pub status: JobStatus,
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub chapters_done: usize,
+    pub chapters_total: usize,
+    pub error: Option<String>,
+    #[serde(skip)]
+    pub result: Option<Value>,
+}
+
+impl Job {
+    fn new(slug: &str, request: Value) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            slug: slug.to_string(),
+            request,
+            status: JobStatus::Queued,
+            created_at: now(),
+            started_at: None,
+            finished_at: None,
+            chapters_done: 0,
+            chapters_total: 0,
+            error: None,
+            result: None,
+        }
+    }
+}
+
+/// Storage of scrapping jobs, `result` is the final callback payload of succeeded job
+pub trait JobStore: Send + Sync {
+    fn create(&self, slug: &str, request: Value) -> Result<Job, Error>;
+
+    /// Reusable job created since `since` with the same `idempotency_key` in its request,
+    /// otherwise a new one. The flag tells whether the job is new
+    fn create_idempotent(
+        &self,
+        slug: &str,
+        request: Value,
+        key: &str,
+        since: u64,
+    ) -> Result<(Job, bool), Error>;
+
+    fn start(&self, id: &str) -> Result<(), Error>;
+
+    fn progress(&self, id: &str, chapters_done: usize, chapters_total: usize) -> Result<(), Error>;
+
+    fn succeed(&self, id: &str, result: Value) -> Result<(), Error>;
+
+    fn fail(&self, id: &str, error: &str) -> Result<(), Error>;
+
+    fn cancel(&self, id: &str, error: &str) -> Result<(), Error>;
+
+    fn get(&self, id: &str) -> Result<Option<Job>, Error>;
+
+    /// Latest jobs first
+    fn list(&self, filter: &JobFilter) -> Result<Vec<Job>, Error>;
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JobFilter {
+    pub slug: Option<String>,
+    pub status: Option<JobStatus>,
+    pub limit: Option<usize>,
+}
+
+impl JobFilter {
+    const DEFAULT_LIMIT: usize = 100;
+
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(Self::DEFAULT_LIMIT)
+    }
+
+    fn matches(&self, job: &Job) -> bool {
+        self.slug.as_ref().is_none_or(|slug| *slug == job.slug)
+            && self.status.is_none_or(|status| status == job.status)
+    }
+}
+
+const INTERRUPTED_ERROR: &str = "Interrupted by restart";
+
+/// SQLite database at `JOB_STORE_PATH`, or in-memory store when it is `memory`
+pub fn from_env() -> Result<Arc<dyn JobStore>, Error> {
+    let path =
+        env::var("JOB_STORE_PATH").unwrap_or_else(|_| config::DEFAULT_JOB_STORE_PATH.to_string());
+    if path == "memory" {
+        return Ok(Arc::new(MemoryJobStore::default()));
+    }
+
+    let store = SqliteJobStore::open(&path)?;
+    let interrupted = store.fail_unfinished(INTERRUPTED_ERROR)?;
+    if interrupted > 0 {
+        warn!(
+            jobs = interrupted,
+            "Unfinished jobs of the previous run are marked failed"
+        );
+    }
+
+    Ok(Arc::new(store))
+}
+
+static IDEMPOTENCY_WINDOW_SECONDS: Lazy<u64> = Lazy::new(|| {
+    env::var("IDEMPOTENCY_WINDOW_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(config::DEFAULT_IDEMPOTENCY_WINDOW_SECONDS)
+});
+
+pub enum Accepted {
+    New(JobTracker),
+    /// Job of the earlier request with the same idempotency key
+    Duplicate(Box<Job>),
+}
+
+/// Job lifecycle reporter for processing, store errors are logged and never fail the job
+#[derive(Clone)]
+pub struct JobTracker {
+    id: String,
+    store: Arc<dyn JobStore>,
+    cancellation: Cancellation,
+}
+
+impl JobTracker {
+    /// The job can be cancelled by id with `cancellation::cancel` until it finishes
+    pub fn new(id: &str, store: Arc<dyn JobStore>, cancellation: CancellationToken) -> Self {
+        let cancellation = Cancellation::new(cancellation);
+        cancellation::register(id, &cancellation);
+
+        Self {
+            id: id.to_string(),
+            store,
+            cancellation,
+        }
+    }
+
+    /// Records a new queued job for the request unless `key` was already used
+    /// within `IDEMPOTENCY_WINDOW_SECONDS`
+    pub fn accept<T: Serialize>(
+        store: &Arc<dyn JobStore>,
+        slug: &str,
+        request: &T,
+        key: Option<&str>,
+        parent: &CancellationToken,
+    ) -> Result<Accepted, Error> {
+        let Some(key) = key else {
+            return Ok(Accepted::New(Self::create(store, slug, request, parent)?));
+        };
+
+        let since = now().saturating_sub(*IDEMPOTENCY_WINDOW_SECONDS);
+        let (job, is_new) =
+            store.create_idempotent(slug, serde_json::to_value(request)?, key, since)?;
+        if !is_new {
+            return Ok(Accepted::Duplicate(Box::new(job)));
+        }
+        events::register(&job.id);
+
+        Ok(Accepted::New(Self::new(
+            &job.id,
+            store.clone(),
+            parent.child_token(),
+        )))
+    }
+
+    /// Records a new queued job for the request, the job is cancelled together with `parent`
+    pub fn create<T: Serialize>(
+        store: &Arc<dyn JobStore>,
+        slug: &str,
+        request: &T,
+        parent: &CancellationToken,
+    ) -> Result<Self, Error> {
+        let job = store.create(slug, serde_json::to_value(request)?)?;
+        events::register(&job.id);
+
+        Ok(Self::new(&job.id, store.clone(), parent.child_token()))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// `None` when the job is not cancelled or is cancelled on shutdown
+    pub fn cancel_reason(&self) -> Option<Reason> {
+        self.cancellation.reason()
+    }
+
+    pub fn cancel(&self, reason: Reason) {
+        self.cancellation.cancel(reason);
+    }
+
+    pub fn stop(&self) {
+        self.cancellation.stop();
+    }
+
+    pub async fn cancelled(&self) {
+        self.cancellation.cancelled().await;
+    }
+
+    pub fn start(&self) {
+        self.log(self.store.start(&self.id));
+    }
+
+    pub fn progress(&self, chapters_done: usize, chapters_total: usize) {
+        self.log(self.store.progress(&self.id, chapters_done, chapters_total));
+    }
+
+    pub fn succeed<T: Serialize>(&self, result: &T) {
+        match serde_json::to_value(result) {
+            Ok(result) => self.log(self.store.succeed(&self.id, result)),
+            Err(err) => self.log(Err(err.into())),
+        }
+        self.event(JobEvent::Succeeded);
+        cancellation::unregister(&self.id);
+    }
+
+    /// Job cancelled on request is recorded as `cancelled` rather than `failed`
+    pub fn fail(&self, error: &str) {
+        if let Some(Reason::Requested { .. }) = self.cancel_reason() {
+            self.log(self.store.cancel(&self.id, error));
+            self.event(JobEvent::Cancelled);
+        } else {
+            self.log(self.store.fail(&self.id, error));
+            self.event(JobEvent::Failed {
+                error: error.to_string(),
+            });
+        }
+        cancellation::unregister(&self.id);
+    }
+
+    /// Sends the event to subscribers of `/jobs/{id}/events`
+    pub fn event(&self, event: JobEvent) {
+        events::publish(&self.id, event);
+    }
+
+    fn log(&self, result: Result<(), Error>) {
+        if let Err(err) = result {
+            error!(job_id = self.id, "Failed to update job: {err:?}");
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stores() -> Vec<Arc<dyn JobStore>> {
+        vec![
+            Arc::new(MemoryJobStore::default()),
+            Arc::new(SqliteJobStore::open(":memory:").expect("in-memory SQLite opens")),
+        ]
+    }
+
+    fn keyed(key: &str) -> Value {
+        json!({ "slug": "manga", "idempotency_key": key })
+    }
+
+    #[test]
+    fn job_goes_through_statuses() {
+        for store in stores() {
+            let job = store.create("manga", json!({})).unwrap();
+            assert_eq!(job.status, JobStatus::Queued);
+
+            store.start(&job.id).unwrap();
+            store.progress(&job.id, 2, 5).unwrap();
+            let running = store.get(&job.id).unwrap().unwrap();
+            assert_eq!(running.status, JobStatus::Running);
+            assert!(running.started_at.is_some());
+            assert_eq!((running.chapters_done, running.chapters_total), (2, 5));
+
+            store.succeed(&job.id, json!({ "slug": "manga" })).unwrap();
+            let succeeded = store.get(&job.id).unwrap().unwrap();
+            assert_eq!(succeeded.status, JobStatus::Succeeded);
+            assert!(succeeded.finished_at.is_some());
+            assert_eq!(succeeded.result, Some(json!({ "slug": "manga" })));
+        }
+    }
+
+    #[test]
+    fn failed_and_cancelled_jobs_keep_error() {
+        for store in stores() {
+            let failed = store.create("manga", json!({})).unwrap();
+            store.fail(&failed.id, "boom").unwrap();
+            let failed = store.get(&failed.id).unwrap().unwrap();
+            assert_eq!(failed.status, JobStatus::Failed);
+            assert_eq!(failed.error.as_deref(), Some("boom"));
+
+            let cancelled = store.create("manga", json!({})).unwrap();
+            store.cancel(&cancelled.id, "stop").unwrap();
+            let cancelled = store.get(&cancelled.id).unwrap().unwrap();
+            assert_eq!(cancelled.status, JobStatus::Cancelled);
+            assert_eq!(cancelled.error.as_deref(), Some("stop"));
+        }
+    }
+
+    #[test]
+    fn unknown_job_is_not_found() {
+        for store in stores() {
+            assert!(matches!(store.start("missing"), Err(Error::NotFound(_))));
+            assert!(store.get("missing").unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn list_is_filtered_by_status_and_slug() {
+        for store in stores() {
+            let first = store.create("first", json!({})).unwrap();
+            store.create("second", json!({})).unwrap();
+            store.start(&first.id).unwrap();
+
+            let filter = JobFilter {
+                status: Some(JobStatus::Running),
+                ..JobFilter::default()
+            };
+            let running = store.list(&filter).unwrap();
+            assert_eq!(running.len(), 1);
+            assert_eq!(running[0].id, first.id);
+
+            let filter = JobFilter {
+                slug: Some("second".to_string()),
+                ..JobFilter::default()
+            };
+            assert_eq!(store.list(&filter).unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn repeated_key_gets_the_same_job() {
+        for store in stores() {
+            let (job, is_new) = store
+                .create_idempotent("manga", keyed("key"), "key", 0)
+                .unwrap();
+            assert!(is_new);
+
+            store.start(&job.id).unwrap();
+            let (running, is_new) = store
+                .create_idempotent("manga", keyed("key"), "key", 0)
+                .unwrap();
+            assert!(!is_new);
+            assert_eq!(running.id, job.id);
+
+            store.succeed(&job.id, json!({})).unwrap();
+            let (succeeded, is_new) = store
+                .create_idempotent("manga", keyed("key"), "key", 0)
+                .unwrap();
+            assert!(!is_new);
+            assert_eq!(succeeded.id, job.id);
+
+            let (other, is_new) = store
+                .create_idempotent("manga", keyed("other"), "other", 0)
+                .unwrap();
+            assert!(is_new);
+            assert_ne!(other.id, job.id);
+        }
+    }
+
+    #[test]
+    fn key_outside_of_window_gets_new_job() {
+        for store in stores() {
+            let (job, _) = store
+                .create_idempotent("manga", keyed("key"), "key", 0)
+                .unwrap();
+            let (later, is_new) = store
+                .create_idempotent("manga", keyed("key"), "key", job.created_at + 1)
+                .unwrap();
+            assert!(is_new);
+            assert_ne!(later.id, job.id);
+        }
+    }
+
+    #[test]
+    fn failed_or_cancelled_job_is_redone_for_key() {
+        for store in stores() {
+            let (failed, _) = store
+                .create_idempotent("manga", keyed("failed"), "failed", 0)
+                .unwrap();
+            store.fail(&failed.id, "boom").unwrap();
+            let (redone, is_new) = store
+                .create_idempotent("manga", keyed("failed"), "failed", 0)
+                .unwrap();
+            assert!(is_new);
+            assert_ne!(redone.id, failed.id);
+
+            let (cancelled, _) = store
+                .create_idempotent("manga", keyed("cancelled"), "cancelled", 0)
+                .unwrap();
+            store.cancel(&cancelled.id, "stop").unwrap();
+            let (redone, is_new) = store
+                .create_idempotent("manga", keyed("cancelled"), "cancelled", 0)
+                .unwrap();
+            assert!(is_new);
+            assert_ne!(redone.id, cancelled.id);
+        }
+    }
+
+    #[test]
+    fn unfinished_jobs_are_failed_and_redone_for_key() {
+        let store = SqliteJobStore::open(":memory:").unwrap();
+        let (queued, _) = store
+            .create_idempotent("manga", keyed("queued"), "queued", 0)
+            .unwrap();
+        let (running, _) = store
+            .create_idempotent("manga", keyed("running"), "running", 0)
+            .unwrap();
+        store.start(&running.id).unwrap();
+        let succeeded = store.create("manga", json!({})).unwrap();
+        store.succeed(&succeeded.id, json!({})).unwrap();
+
+        assert_eq!(store.fail_unfinished(INTERRUPTED_ERROR).unwrap(), 2);
+        for job in [&queued, &running] {
+            let job = store.get(&job.id).unwrap().unwrap();
+            assert_eq!(job.status, JobStatus::Failed);
+            assert_eq!(job.error.as_deref(), Some(INTERRUPTED_ERROR));
+        }
+        let succeeded = store.get(&succeeded.id).unwrap().unwrap();
+        assert_eq!(succeeded.status, JobStatus::Succeeded);
+
+        let (job, is_new) = store
+            .create_idempotent("manga", keyed("running"), "running", 0)
+            .unwrap();
+        assert!(is_new);
+        assert_ne!(job.id, running.id);
+    }
+}
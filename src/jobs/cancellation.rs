@@ -0,0 +1,78 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio_util::sync::CancellationToken;
+
+static JOBS: Lazy<Mutex<HashMap<String, Cancellation>>> = Lazy::new(Mutex::default);
+
+/// Why the job was cancelled, jobs cancelled on shutdown have no reason
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// `callback` tells whether the failure callback is still sent
+    Requested { callback: bool },
+    /// Deadline of the job is over
+    TimedOut,
+}
+
+/// Cancellation of one job, shared by all clones of its tracker
+#[derive(Clone)]
+pub struct Cancellation {
+    token: CancellationToken,
+    reason: Arc<OnceLock<Reason>>,
+}
+
+impl Cancellation {
+    pub fn new(token: CancellationToken) -> Self {
+        Self {
+            token,
+            reason: Arc::default(),
+        }
+    }
+
+    /// The first reason is kept when the job is cancelled more than once
+    pub fn cancel(&self, reason: Reason) {
+        let _ = self.reason.set(reason);
+        self.token.cancel();
+    }
+
+    /// Stops the tasks of a job that already failed, it is not a cancellation of the job
+    pub fn stop(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub fn reason(&self) -> Option<Reason> {
+        self.reason.get().copied()
+    }
+
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await;
+    }
+}
+
+pub fn register(id: &str, cancellation: &Cancellation) {
+    if let Ok(mut jobs) = JOBS.lock() {
+        jobs.insert(id.to_string(), cancellation.clone());
+    }
+}
+
+pub fn unregister(id: &str) {
+    if let Ok(mut jobs) = JOBS.lock() {
+        jobs.remove(id);
+    }
+}
+
+/// `false` when the job is not queued or running in this process
+pub fn cancel(id: &str, reason: Reason) -> bool {
+    let cancellation = JOBS.lock().ok().and_then(|jobs| jobs.get(id).cloned());
+    match cancellation {
+        Some(cancellation) => {
+            cancellation.cancel(reason);
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,86 @@
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+const CHANNEL_CAPACITY: usize = 256;
+
+static CHANNELS: Lazy<Mutex<HashMap<String, Sender<JobEvent>>>> = Lazy::new(Mutex::default);
+
+/// Live progress of a running job, `succeeded`, `failed` and `cancelled` are the last events of the job
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobEvent {
+    ChaptersFetched {
+        chapters_total: usize,
+        /// Count of gaps in chapter numbers
+        missing_chapters: usize,
+    },
+    ChapterResolved {
+        chapter: String,
+        volume: String,
+        images_count: usize,
+        chapters_done: usize,
+        chapters_total: usize,
+    },
+    Retry {
+        chapter: String,
+        volume: String,
+        attempt: u32,
+        error: String,
+    },
+    ChapterFailed {
+        chapter: String,
+        volume: String,
+        reason: String,
+    },
+    CallbackSent {
+        url: String,
+        /// `false` when the callback is queued in the outbox for redelivery
+        delivered: bool,
+    },
+    Succeeded,
+    Failed {
+        error: String,
+    },
+    Cancelled,
+}
+
+impl JobEvent {
+    pub const fn is_final(&self) -> bool {
+        matches!(
+            self,
+            Self::Succeeded | Self::Failed { .. } | Self::Cancelled
+        )
+    }
+}
+
+pub fn register(id: &str) {
+    if let Ok(mut channels) = CHANNELS.lock() {
+        channels
+            .entry(id.to_string())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0);
+    }
+}
+
+/// `None` when the job is not running in this process
+pub fn subscribe(id: &str) -> Option<Receiver<JobEvent>> {
+    CHANNELS.lock().ok()?.get(id).map(Sender::subscribe)
+}
+
+/// Final event closes the channel, so subscribers stop after receiving it
+pub fn publish(id: &str, event: JobEvent) {
+    let Ok(mut channels) = CHANNELS.lock() else {
+        return;
+    };
+
+    let is_final = event.is_final();
+    if let Some(sender) = channels.get(id) {
+        // Nobody listens most of the time
+        let _ = sender.send(event);
+    }
+    if is_final {
+        channels.remove(id);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::jobs::{Error, Job, JobFilter, JobStatus, JobStore, now};
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub struct MemoryJobStore {
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl MemoryJobStore {
+    fn update<F: FnOnce(&mut Job)>(&self, id: &str, update: F) -> Result<(), Error> {
+        let mut jobs = self.jobs.lock().map_err(|_| Error::Lock)?;
+        let job = jobs
+            .get_mut(id)
+            .ok_or_else(|| Error::NotFound(id.to_string()))?;
+        update(job);
+
+        Ok(())
+    }
+}
+
+impl JobStore for MemoryJobStore {
+    fn create(&self, slug: &str, request: Value) -> Result<Job, Error> {
+        let job = Job::new(slug, request);
+        self.jobs
+            .lock()
+            .map_err(|_| Error::Lock)?
+            .insert(job.id.clone(), job.clone());
+
+        Ok(job)
+    }
+
+    fn create_idempotent(
+        &self,
+        slug: &str,
+        request: Value,
+        key: &str,
+        since: u64,
+    ) -> Result<(Job, bool), Error> {
+        let mut jobs = self.jobs.lock().map_err(|_| Error::Lock)?;
+        let existing = jobs
+            .values()
+            .filter(|job| {
+                job.created_at >= since
+                    && job.request["idempotency_key"] == key
+                    && job.status.is_reusable()
+            })
+            .max_by_key(|job| job.created_at);
+        if let Some(job) = existing {
+            return Ok((job.clone(), false));
+        }
+
+        let job = Job::new(slug, request);
+        jobs.insert(job.id.clone(), job.clone());
+
+        Ok((job, true))
+    }
+
+    fn start(&self, id: &str) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.status = JobStatus::Running;
+            job.started_at = Some(now());
+        })
+    }
+
+    fn progress(&self, id: &str, chapters_done: usize, chapters_total: usize) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.chapters_done = chapters_done;
+            job.chapters_total = chapters_total;
+        })
+    }
+
+    fn succeed(&self, id: &str, result: Value) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.status = JobStatus::Succeeded;
+            job.finished_at = Some(now());
+            job.result = Some(result);
+        })
+    }
+
+    fn fail(&self, id: &str, error: &str) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.status = JobStatus::Failed;
+            job.finished_at = Some(now());
+            job.error = Some(error.to_string());
+        })
+    }
+
+    fn cancel(&self, id: &str, error: &str) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.status = JobStatus::Cancelled;
+            job.finished_at = Some(now());
+            job.error = Some(error.to_string());
+        })
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Job>, Error> {
+        Ok(self.jobs.lock().map_err(|_| Error::Lock)?.get(id).cloned())
+    }
+
+    fn list(&self, filter: &JobFilter) -> Result<Vec<Job>, Error> {
+        let mut jobs: Vec<Job> = self
+            .jobs
+            .lock()
+            .map_err(|_| Error::Lock)?
+            .values()
+            .filter(|job| filter.matches(job))
+            .cloned()
+            .collect();
+        jobs.sort_by_key(|job| Reverse(job.created_at));
+        jobs.truncate(filter.limit());
+
+        Ok(jobs)
+    }
+}
@@ -0,0 +1,263 @@
+use crate::jobs::{Error, Job, JobFilter, JobStatus, JobStore, now};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS jobs (
+        id TEXT PRIMARY KEY,
+        slug TEXT NOT NULL,
+        request TEXT NOT NULL,
+        status TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        started_at INTEGER,
+        finished_at INTEGER,
+        chapters_done INTEGER NOT NULL DEFAULT 0,
+        chapters_total INTEGER NOT NULL DEFAULT 0,
+        error TEXT,
+        result TEXT
+    );
+    CREATE INDEX IF NOT EXISTS jobs_slug_status ON jobs (slug, status);
+    CREATE INDEX IF NOT EXISTS jobs_idempotency_key
+        ON jobs (json_extract(request, '$.idempotency_key'));
+";
+
+const COLUMNS: &str = "id, slug, request, status, created_at, started_at, finished_at, \
+    chapters_done, chapters_total, error, result";
+
+pub struct SqliteJobStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteJobStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        Self::new(Connection::open(path)?)
+    }
+
+    fn new(connection: Connection) -> Result<Self, Error> {
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Jobs left queued or running by a stopped process are never finished, so they are marked failed
+    /// on startup, and a requeued message or repeated request with their idempotency key is processed again
+    pub fn fail_unfinished(&self, error: &str) -> Result<usize, Error> {
+        Ok(self.connection()?.execute(
+            "UPDATE jobs SET status = ?1, finished_at = ?2, error = ?3 WHERE status IN (?4, ?5)",
+            params![
+                JobStatus::Failed.as_str(),
+                now(),
+                error,
+                JobStatus::Queued.as_str(),
+                JobStatus::Running.as_str()
+            ],
+        )?)
+    }
+
+    fn connection(&self) -> Result<MutexGuard<'_, Connection>, Error> {
+        self.connection.lock().map_err(|_| Error::Lock)
+    }
+
+    fn execute<P: rusqlite::Params>(&self, id: &str, sql: &str, params: P) -> Result<(), Error> {
+        match self.connection()?.execute(sql, params)? {
+            0 => Err(Error::NotFound(id.to_string())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl JobStore for SqliteJobStore {
+    fn create(&self, slug: &str, request: Value) -> Result<Job, Error> {
+        let job = Job::new(slug, request);
+        insert(&*self.connection()?, &job)?;
+
+        Ok(job)
+    }
+
+    fn create_idempotent(
+        &self,
+        slug: &str,
+        request: Value,
+        key: &str,
+        since: u64,
+    ) -> Result<(Job, bool), Error> {
+        // Lookup and insert are under the same connection lock, so concurrent requests get one job
+        let connection = self.connection()?;
+        let existing = connection
+            .query_row(
+                &format!(
+                    "SELECT {COLUMNS} FROM jobs \
+                    WHERE json_extract(request, '$.idempotency_key') = ?1 AND created_at >= ?2 \
+                    AND status IN (?3, ?4, ?5) \
+                    ORDER BY created_at DESC LIMIT 1"
+                ),
+                params![
+                    key,
+                    since,
+                    JobStatus::Queued.as_str(),
+                    JobStatus::Running.as_str(),
+                    JobStatus::Succeeded.as_str()
+                ],
+                row_to_raw_job,
+            )
+            .optional()?;
+        if let Some(job) = existing {
+            return Ok((job.into_job()?, false));
+        }
+
+        let job = Job::new(slug, request);
+        insert(&connection, &job)?;
+
+        Ok((job, true))
+    }
+
+    fn start(&self, id: &str) -> Result<(), Error> {
+        self.execute(
+            id,
+            "UPDATE jobs SET status = ?2, started_at = ?3 WHERE id = ?1",
+            params![id, JobStatus::Running.as_str(), now()],
+        )
+    }
+
+    fn progress(&self, id: &str, chapters_done: usize, chapters_total: usize) -> Result<(), Error> {
+        self.execute(
+            id,
+            "UPDATE jobs SET chapters_done = ?2, chapters_total = ?3 WHERE id = ?1",
+            params![id, chapters_done, chapters_total],
+        )
+    }
+
+    fn succeed(&self, id: &str, result: Value) -> Result<(), Error> {
+        self.execute(
+            id,
+            "UPDATE jobs SET status = ?2, finished_at = ?3, result = ?4 WHERE id = ?1",
+            params![id, JobStatus::Succeeded.as_str(), now(), result.to_string()],
+        )
+    }
+
+    fn fail(&self, id: &str, error: &str) -> Result<(), Error> {
+        self.execute(
+            id,
+            "UPDATE jobs SET status = ?2, finished_at = ?3, error = ?4 WHERE id = ?1",
+            params![id, JobStatus::Failed.as_str(), now(), error],
+        )
+    }
+
+    fn cancel(&self, id: &str, error: &str) -> Result<(), Error> {
+        self.execute(
+            id,
+            "UPDATE jobs SET status = ?2, finished_at = ?3, error = ?4 WHERE id = ?1",
+            params![id, JobStatus::Cancelled.as_str(), now(), error],
+        )
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Job>, Error> {
+        let job = self
+            .connection()?
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM jobs WHERE id = ?1"),
+                params![id],
+                row_to_raw_job,
+            )
+            .optional()?;
+
+        job.map(RawJob::into_job).transpose()
+    }
+
+    fn list(&self, filter: &JobFilter) -> Result<Vec<Job>, Error> {
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(&format!(
+            "SELECT {COLUMNS} FROM jobs \
+            WHERE (?1 IS NULL OR slug = ?1) AND (?2 IS NULL OR status = ?2) \
+            ORDER BY created_at DESC LIMIT ?3"
+        ))?;
+        let jobs = statement
+            .query_map(
+                params![
+                    filter.slug,
+                    filter.status.map(JobStatus::as_str),
+                    filter.limit()
+                ],
+                row_to_raw_job,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        jobs.into_iter().map(RawJob::into_job).collect()
+    }
+}
+
+fn insert(connection: &Connection, job: &Job) -> Result<(), Error> {
+    connection.execute(
+        "INSERT INTO jobs (id, slug, request, status, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            job.id,
+            job.slug,
+            job.request.to_string(),
+            job.status.as_str(),
+            job.created_at
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Row values before JSON columns and status are parsed
+struct RawJob {
+    id: String,
+    slug: String,
+    request: String,
+    status: String,
+    created_at: u64,
+    started_at: Option<u64>,
+    finished_at: Option<u64>,
+    chapters_done: usize,
+    chapters_total: usize,
+    error: Option<String>,
+    result: Option<String>,
+}
+
+impl RawJob {
+    fn into_job(self) -> Result<Job, Error> {
+        Ok(Job {
+            id: self.id,
+            slug: self.slug,
+            request: serde_json::from_str(&self.request)?,
+            status: self.status.parse()?,
+            created_at: self.created_at,
+            started_at: self.started_at,
+            finished_at: self.finished_at,
+            chapters_done: self.chapters_done,
+            chapters_total: self.chapters_total,
+            error: self.error,
+            result: self
+                .result
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?,
+        })
+    }
+}
+
+fn row_to_raw_job(row: &Row) -> rusqlite::Result<RawJob> {
+    Ok(RawJob {
+        id: row.get(0)?,
+        slug: row.get(1)?,
+        request: row.get(2)?,
+        status: row.get(3)?,
+        created_at: row.get(4)?,
+        started_at: row.get(5)?,
+        finished_at: row.get(6)?,
+        chapters_done: row.get(7)?,
+        chapters_total: row.get(8)?,
+        error: row.get(9)?,
+        result: row.get(10)?,
+    })
+}
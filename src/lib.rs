@@ -0,0 +1,3 @@
+//! Helpers for services receiving callbacks from the parser
+
+pub mod signature;
@@ -1,13 +1,17 @@
 use command::process_commands;
 use tracing::error;
 
+mod callback;
 mod command;
 mod config;
+mod jobs;
 mod mangalib;
+mod metrics;
 mod processing;
 mod rabbitmq_consumer;
 mod send_resource;
 mod server;
+mod shutdown;
 
 #[tokio::main]
 async fn main() -> Result<(), command::Error> {
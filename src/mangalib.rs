@@ -2,13 +2,20 @@
 #![allow(unused_variables)]
 
 mod builder;
+pub mod chapter_key;
 pub mod search;
+pub mod tabs;
 
 use crate::mangalib::builder::Builder;
+pub use crate::mangalib::builder::USER_AGENT;
+use crate::mangalib::chapter_key::ChapterKey;
+use crate::mangalib::tabs::Tabs;
+use crate::metrics;
 use headless_chrome::{Browser, LaunchOptions};
 use serde::{Deserialize, Deserializer, Serialize};
 use thiserror::Error;
 use tracing::debug;
+use utoipa::ToSchema;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -53,7 +60,7 @@ struct ImageInner {
     ratio: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct MangaPreview {
     #[serde(rename(deserialize = "manga_type"))]
     pub r#type: String,
@@ -63,7 +70,7 @@ pub struct MangaPreview {
     pub image_url: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, ToSchema)]
 pub struct MangaChapter {
     #[serde(deserialize_with = "to_string")]
     pub chapter_volume: String,
@@ -78,6 +85,10 @@ impl MangaChapter {
             chapter_number: number.into(),
         }
     }
+
+    pub fn key(&self) -> ChapterKey {
+        ChapterKey::new(&self.chapter_volume, &self.chapter_number)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -97,6 +108,7 @@ pub struct HeadlessBrowserClient {
     platform: String,
     image_server_prefix: String,
     base_url: String,
+    tabs: Tabs,
 }
 
 impl HeadlessBrowserClient {
@@ -109,9 +121,7 @@ impl HeadlessBrowserClient {
         T: for<'de> Deserialize<'de>,
     {
         let browser = Self::get_browser()?;
-        let tab = browser
-            .new_tab()
-            .map_err(|err| Error::BrowserTabCreate(err.to_string()))?;
+        let tab = self.tabs.open(&browser)?;
 
         tab.set_user_agent(
             &self.user_agent,
@@ -133,13 +143,21 @@ impl HeadlessBrowserClient {
         Ok(serde_json::from_str(&text)?)
     }
 
-    fn get_browser() -> Result<Browser, Error> {
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn get_browser() -> Result<Browser, Error> {
         let options = LaunchOptions::default_builder()
             .sandbox(false)
             .build()
             .map_err(|err| Error::BrowserCreateBuilder(err.to_string()))?;
 
-        Browser::new(options).map_err(|err| Error::BrowserCreate(err.to_string()))
+        metrics::CHROME_LAUNCHES.inc();
+        Browser::new(options).map_err(|err| {
+            metrics::CHROME_LAUNCH_FAILURES.inc();
+            Error::BrowserCreate(err.to_string())
+        })
     }
 }
 
@@ -161,7 +179,11 @@ impl Client for HeadlessBrowserClient {
             url = url,
             "Searching manga chapter image urls",
         );
+        let timer = metrics::PARSE_DURATION
+            .with_label_values(&["chapter_images"])
+            .start_timer();
         let image_inner_list: ImageInnerList = self.parse(url)?;
+        timer.observe_duration();
 
         let images = image_inner_list
             .data
@@ -176,7 +198,11 @@ impl Client for HeadlessBrowserClient {
     fn get_manga_chapters(&self, slug: &str) -> Result<Vec<MangaChapter>, Error> {
         let url = &format!("{}/api/manga/{slug}/chapters", self.base_url);
         debug!(manga_slug = slug, url = url, "Searching manga chapters",);
+        let timer = metrics::PARSE_DURATION
+            .with_label_values(&["chapters"])
+            .start_timer();
         let chapter_inner_list: ChapterInnerList = self.parse(url)?;
+        timer.observe_duration();
 
         debug!(
             manga_slug = slug,
@@ -1,10 +1,11 @@
-const USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.114 Safari/537.36";
+pub const USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.114 Safari/537.36";
 const ACCEPT_LANGUAGE: &str = "en-US,en;q=0.9,hi;q=0.8,es;q=0.7,lt;q=0.6";
 const PLATFORM: &str = "macOS";
 const IMAGE_SERVER_PREFIX: &str = "https://img33.imgslib.link";
 const MANGALIB_DEFAULT_BASE_URL: &str = "https://api.cdnlibs.org";
 
 use crate::mangalib::HeadlessBrowserClient;
+use crate::mangalib::tabs::Tabs;
 
 #[derive(Default, Debug)]
 pub struct Builder {
@@ -13,6 +14,7 @@ pub struct Builder {
     platform: Option<String>,
     image_server_prefix: Option<String>,
     base_url: Option<String>,
+    tabs: Option<Tabs>,
 }
 
 impl Builder {
@@ -41,6 +43,12 @@ impl Builder {
         self
     }
 
+    /// Tabs of the client are closed together with these
+    pub fn tabs(mut self, tabs: &Tabs) -> Self {
+        self.tabs = Some(tabs.clone());
+        self
+    }
+
     pub fn build(self) -> HeadlessBrowserClient {
         HeadlessBrowserClient {
             user_agent: self.user_agent.unwrap_or_else(|| USER_AGENT.to_string()),
@@ -54,6 +62,7 @@ impl Builder {
             base_url: self
                 .base_url
                 .unwrap_or_else(|| MANGALIB_DEFAULT_BASE_URL.to_string()),
+            tabs: self.tabs.unwrap_or_default(),
         }
     }
 }
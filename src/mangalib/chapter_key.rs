@@ -0,0 +1,153 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+/// Comparable position of the chapter, ordered by volume and then by chapter number
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChapterKey {
+    volume: ChapterNumber,
+    number: ChapterNumber,
+}
+
+impl ChapterKey {
+    pub fn new(volume: &str, number: &str) -> Self {
+        Self {
+            volume: ChapterNumber::parse_volume(volume),
+            number: ChapterNumber::parse(number),
+        }
+    }
+
+    pub const fn volume(&self) -> &ChapterNumber {
+        &self.volume
+    }
+
+    pub const fn number(&self) -> &ChapterNumber {
+        &self.number
+    }
+}
+
+/// Decimal chapter or volume number, `10.5` goes after `10` and before `11`.
+///
+/// Anything after the number, like `10 extra`, makes an extra chapter placed right after `10`.
+/// Values without number at all, like `extra`, go after all numbered ones.
+#[derive(Debug, Clone)]
+pub struct ChapterNumber {
+    value: f64,
+    extra: Option<String>,
+}
+
+impl ChapterNumber {
+    pub fn parse(value: &str) -> Self {
+        let value = value.trim().replace(',', ".");
+        let numeric_len = value
+            .char_indices()
+            .take_while(|(index, char)| char.is_ascii_digit() || (*index > 0 && *char == '.'))
+            .count();
+        let (numeric, rest) = value.split_at(numeric_len);
+        let rest = rest.trim_matches(|char: char| char.is_whitespace() || "-_.#".contains(char));
+
+        match numeric.trim_end_matches('.').parse::<f64>() {
+            Ok(number) => Self {
+                value: number,
+                extra: (!rest.is_empty()).then(|| rest.to_lowercase()),
+            },
+            Err(_) => Self {
+                value: f64::INFINITY,
+                extra: Some(value.to_lowercase()),
+            },
+        }
+    }
+
+    /// Chapters without volume come as empty or `0` volume, both are volume `0`
+    pub fn parse_volume(value: &str) -> Self {
+        if value.trim().is_empty() {
+            return Self::parse("0");
+        }
+
+        Self::parse(value)
+    }
+
+    /// Integer part of a regular chapter number, `None` for extra chapters
+    pub fn whole(&self) -> Option<u64> {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        (self.extra.is_none() && self.value.is_finite()).then(|| self.value.trunc() as u64)
+    }
+}
+
+impl PartialEq for ChapterNumber {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ChapterNumber {}
+
+impl PartialOrd for ChapterNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ChapterNumber {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value
+            .total_cmp(&other.value)
+            .then_with(|| self.extra.cmp(&other.extra))
+    }
+}
+
+impl Hash for ChapterNumber {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.to_bits().hash(state);
+        self.extra.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_decimal_numbers() {
+        assert_eq!(ChapterNumber::parse("10").whole(), Some(10));
+        assert_eq!(ChapterNumber::parse("10.5").whole(), Some(10));
+        assert_eq!(ChapterNumber::parse("10,5"), ChapterNumber::parse("10.5"));
+        assert_eq!(ChapterNumber::parse(" 7. "), ChapterNumber::parse("7"));
+    }
+
+    #[test]
+    fn extra_chapters_have_no_whole_number() {
+        assert_eq!(ChapterNumber::parse("10 extra").whole(), None);
+        assert_eq!(ChapterNumber::parse("extra").whole(), None);
+        assert_eq!(
+            ChapterNumber::parse("10 - Extra"),
+            ChapterNumber::parse("10 extra")
+        );
+    }
+
+    #[test]
+    fn orders_by_number_and_extra() {
+        let mut numbers = ["extra", "11", "10 extra", "10.5", "2", "10"]
+            .map(ChapterNumber::parse)
+            .to_vec();
+        numbers.sort();
+
+        let expected = ["2", "10", "10 extra", "10.5", "11", "extra"].map(ChapterNumber::parse);
+        assert_eq!(numbers, expected);
+    }
+
+    #[test]
+    fn empty_volume_is_zero() {
+        assert_eq!(ChapterNumber::parse_volume(""), ChapterNumber::parse("0"));
+        assert_eq!(
+            ChapterNumber::parse_volume(" "),
+            ChapterNumber::parse_volume("0")
+        );
+    }
+
+    #[test]
+    fn keys_are_ordered_by_volume_first() {
+        assert!(ChapterKey::new("1", "20") < ChapterKey::new("2", "1"));
+        assert!(ChapterKey::new("", "5") < ChapterKey::new("1", "1"));
+        assert_eq!(ChapterKey::new("", "5"), ChapterKey::new("0", "5.0"));
+    }
+}
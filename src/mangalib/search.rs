@@ -1,13 +1,24 @@
 use crate::mangalib::MangaPreview;
 use async_stream::stream;
 use futures::Stream;
-use governor::{DefaultKeyedRateLimiter, Quota};
-use serde::Serialize;
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultDirectRateLimiter, DefaultKeyedRateLimiter, Quota, RateLimiter};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use std::num::NonZeroU32;
+use std::time::Duration;
 use thiserror::Error;
 use tracing::{debug, error, info};
+use utoipa::ToSchema;
+
+const REQUESTS_PER_MINUTE: u32 = 30;
+
+/// Shared by all search requests of the web server, mangalib limits requests per client
+static SEARCH_RATE_LIMITER: Lazy<DefaultDirectRateLimiter> =
+    Lazy::new(|| RateLimiter::direct(quota()));
 
 mod response {
+    use super::SearchPage;
     use crate::mangalib::MangaPreview;
     use serde::Deserialize;
 
@@ -97,13 +108,49 @@ mod response {
         }
     }
 
-    impl From<Response> for Vec<MangaPreview> {
+    impl From<Response> for SearchPage {
         fn from(value: Response) -> Self {
-            value
-                .data
-                .into_iter()
-                .map(std::convert::Into::into)
-                .collect()
+            Self {
+                page: value.meta.current_page,
+                has_next_page: value.meta.has_next_page,
+                items: value
+                    .data
+                    .into_iter()
+                    .map(std::convert::Into::into)
+                    .collect(),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchPage {
+    pub page: u32,
+    pub has_next_page: bool,
+    pub items: Vec<MangaPreview>,
+}
+
+/// Mangalib title types, sent as their numeric ids
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MangaType {
+    Manga,
+    Oel,
+    Manhwa,
+    Manhua,
+    Rumanga,
+    Comics,
+}
+
+impl MangaType {
+    const fn id(self) -> u32 {
+        match self {
+            Self::Manga => 1,
+            Self::Oel => 4,
+            Self::Manhwa => 5,
+            Self::Manhua => 6,
+            Self::Rumanga => 8,
+            Self::Comics => 9,
         }
     }
 }
@@ -113,11 +160,19 @@ struct Query {
     fields: Vec<String>,
     site_ids: Vec<u32>,
     page: u32,
+    q: Option<String>,
+    types: Vec<MangaType>,
 }
 
 impl Query {
     fn to_reqwest_format(&self) -> Vec<(String, String)> {
         let mut formatted = vec![];
+        if let Some(q) = &self.q {
+            formatted.push(("q".to_string(), q.to_string()));
+        }
+        for r#type in &self.types {
+            formatted.push(("types[]".to_string(), r#type.id().to_string()));
+        }
         for field in &self.fields {
             formatted.push(("fields[]".to_string(), field.to_string()));
         }
@@ -139,6 +194,8 @@ impl Query {
             ],
             site_ids: vec![1],
             page,
+            q: None,
+            types: vec![],
         }
     }
 }
@@ -151,9 +208,11 @@ pub enum SendingError {
     Deserialize(reqwest::Error),
     #[error("Runtime create error: {0}")]
     RuntimeCreate(#[from] std::io::Error),
+    #[error("Search rate limit is exceeded, retry after {0:?}")]
+    RateLimited(Duration),
 }
 
-async fn send(client: &reqwest::Client, query: &Query) -> Result<Vec<MangaPreview>, SendingError> {
+async fn send(client: &reqwest::Client, query: &Query) -> Result<SearchPage, SendingError> {
     debug!(page = query.page, "Requesting page");
 
     let response = client
@@ -193,21 +252,41 @@ async fn send(client: &reqwest::Client, query: &Query) -> Result<Vec<MangaPrevie
     }
 }
 
+/// One page of the catalog, `q` searches by title
+pub async fn search(
+    q: Option<String>,
+    types: Vec<MangaType>,
+    page: u32,
+) -> Result<SearchPage, SendingError> {
+    // Waiting for the limiter would hold the request and its connection, so it is rejected
+    if let Err(not_until) = SEARCH_RATE_LIMITER.check() {
+        return Err(SendingError::RateLimited(
+            not_until.wait_time_from(DefaultClock::default().now()),
+        ));
+    }
+    let query = Query {
+        q,
+        types,
+        ..Query::new_only_page(page)
+    };
+
+    send(&reqwest::Client::new(), &query).await
+}
+
 pub fn get_manga_iter() -> impl Stream<Item = MangaPreview> {
     stream! {
-        let quota = Quota::per_minute(NonZeroU32::new(30).expect("Bad quota argument"));
-        let rate_limiter = DefaultKeyedRateLimiter::keyed(quota);
+        let rate_limiter = DefaultKeyedRateLimiter::keyed(quota());
         let client = reqwest::Client::new();
 
         let mut page_num = 1;
         loop {
             rate_limiter.until_key_ready(&"default").await;
             if let Ok(page) = send(&client, &Query::new_only_page(page_num)).await {
-                if page.is_empty() {
+                if page.items.is_empty() {
                     break;
                 }
 
-                for manga in page {
+                for manga in page.items {
                     yield manga;
                 }
 
@@ -218,3 +297,7 @@ pub fn get_manga_iter() -> impl Stream<Item = MangaPreview> {
         }
     }
 }
+
+fn quota() -> Quota {
+    Quota::per_minute(NonZeroU32::new(REQUESTS_PER_MINUTE).expect("Bad quota argument"))
+}
@@ -0,0 +1,86 @@
+use crate::mangalib::Error;
+use headless_chrome::{Browser, Tab};
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::warn;
+
+/// Open Chrome tabs of one job, closed all at once when the job is cancelled
+#[derive(Clone, Default)]
+pub struct Tabs {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    tabs: Vec<Arc<Tab>>,
+    closed: bool,
+}
+
+impl Tabs {
+    /// New tab is refused once the tabs are closed, so a tab opened during the cancellation is not left behind
+    pub fn open(&self, browser: &Browser) -> Result<OpenTab, Error> {
+        let tab = browser
+            .new_tab()
+            .map_err(|err| Error::BrowserTabCreate(err.to_string()))?;
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if inner.closed {
+            close(&tab);
+            return Err(Error::BrowserTabCreate("job is cancelled".to_string()));
+        }
+        inner.tabs.push(tab.clone());
+
+        Ok(OpenTab {
+            tabs: self.clone(),
+            tab,
+        })
+    }
+
+    /// Waits of the closed tabs fail, so browsers of the job are released without finishing their pages
+    pub fn close(&self) {
+        let tabs = {
+            let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+            inner.closed = true;
+            std::mem::take(&mut inner.tabs)
+        };
+        for tab in &tabs {
+            close(tab);
+        }
+    }
+
+    fn release(&self, tab: &Arc<Tab>) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.tabs.retain(|open| !Arc::ptr_eq(open, tab));
+    }
+}
+
+impl fmt::Debug for Tabs {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.debug_struct("Tabs").finish_non_exhaustive()
+    }
+}
+
+/// Tab is no longer closed with the job when this is dropped
+pub struct OpenTab {
+    tabs: Tabs,
+    tab: Arc<Tab>,
+}
+
+impl std::ops::Deref for OpenTab {
+    type Target = Tab;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tab
+    }
+}
+
+impl Drop for OpenTab {
+    fn drop(&mut self) {
+        self.tabs.release(&self.tab);
+    }
+}
+
+fn close(tab: &Tab) {
+    if let Err(err) = tab.close(false) {
+        warn!("Failed to close browser tab: {err:?}");
+    }
+}
@@ -0,0 +1,134 @@
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderName, StatusCode};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+};
+use tracing::error;
+
+pub const METRICS_ROUTE: &str = "/metrics";
+
+pub static JOBS_STARTED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("mangalib_jobs_started_total", "Started scrapping jobs")
+        .expect("Failed to register metric")
+});
+
+pub static JOBS_SUCCEEDED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("mangalib_jobs_succeeded_total", "Succeeded scrapping jobs")
+        .expect("Failed to register metric")
+});
+
+pub static JOBS_FAILED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "mangalib_jobs_failed_total",
+        "Failed scrapping jobs by error kind",
+        &["error_kind"]
+    )
+    .expect("Failed to register metric")
+});
+
+pub static CHAPTERS_FETCHED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("mangalib_chapters_fetched_total", "Scrapped chapters")
+        .expect("Failed to register metric")
+});
+
+pub static IMAGE_URLS_COLLECTED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "mangalib_image_urls_collected_total",
+        "Image urls of scrapped chapters"
+    )
+    .expect("Failed to register metric")
+});
+
+pub static CHROME_LAUNCHES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("mangalib_chrome_launches_total", "Chrome launch attempts")
+        .expect("Failed to register metric")
+});
+
+pub static CHROME_LAUNCH_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "mangalib_chrome_launch_failures_total",
+        "Failed Chrome launches"
+    )
+    .expect("Failed to register metric")
+});
+
+/// Duration of mangalib API requests through Chrome, by endpoint
+pub static PARSE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "mangalib_parse_duration_seconds",
+        "Duration of mangalib API requests through Chrome",
+        &["endpoint"],
+        vec![0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0]
+    )
+    .expect("Failed to register metric")
+});
+
+pub static RETRIES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("mangalib_retries_total", "Retried chapter requests")
+        .expect("Failed to register metric")
+});
+
+/// Callback responses by status code, `error` when no response was received
+pub static CALLBACK_DELIVERIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "mangalib_callback_deliveries_total",
+        "Callback requests by response status",
+        &["status"]
+    )
+    .expect("Failed to register metric")
+});
+
+pub static ACTIVE_PERMITS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "mangalib_active_browser_permits",
+        "Chapters being scrapped right now"
+    )
+    .expect("Failed to register metric")
+});
+
+/// Consumed messages by result, `ack`, `nack` or `requeue`
+pub static AMQP_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "mangalib_amqp_messages_total",
+        "Consumed AMQP messages by result",
+        &["result"]
+    )
+    .expect("Failed to register metric")
+});
+
+/// Registers all metrics, so they are exported with zero values before the first job
+pub fn init() {
+    Lazy::force(&JOBS_STARTED);
+    Lazy::force(&JOBS_SUCCEEDED);
+    Lazy::force(&JOBS_FAILED);
+    Lazy::force(&CHAPTERS_FETCHED);
+    Lazy::force(&IMAGE_URLS_COLLECTED);
+    Lazy::force(&CHROME_LAUNCHES);
+    Lazy::force(&CHROME_LAUNCH_FAILURES);
+    Lazy::force(&PARSE_DURATION);
+    Lazy::force(&RETRIES);
+    Lazy::force(&CALLBACK_DELIVERIES);
+    Lazy::force(&ACTIVE_PERMITS);
+    Lazy::force(&AMQP_MESSAGES);
+}
+
+pub async fn handler() -> (StatusCode, [(HeaderName, String); 1], String) {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        error!("Failed to encode metrics: {err:?}");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(CONTENT_TYPE, "text/plain".to_string())],
+            String::new(),
+        );
+    }
+
+    (
+        StatusCode::OK,
+        [(CONTENT_TYPE, encoder.format_type().to_string())],
+        String::from_utf8_lossy(&buffer).into_owned(),
+    )
+}
@@ -1,24 +1,49 @@
+pub mod batch;
+pub mod coalesce;
+pub mod selection;
+pub mod validation;
+
+use crate::callback::{self, Callback, Delivery, Sent};
+use crate::jobs::JobTracker;
+use crate::jobs::cancellation::Reason;
+use crate::jobs::events::JobEvent;
 use crate::mangalib;
 use crate::mangalib::Client;
+use crate::mangalib::tabs::Tabs;
+use crate::metrics;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use selection::ChapterFilter;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
 use tokio::sync::{AcquireError, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::task::AbortOnDropHandle;
 use tracing::{error, info};
+use utoipa::ToSchema;
 
 macro_rules! retry {
-    ($f:expr, $count:expr) => {{
+    ($f:expr, $count:expr, $stop:expr, $on_retry:expr) => {{
         let mut tries = 0;
         let result = loop {
             let result = $f;
             tries += 1;
-            if result.is_ok() || tries >= $count {
+            if result.is_ok() || tries >= $count || $stop {
                 break result;
             }
+            metrics::RETRIES.inc();
+            $on_retry(tries, &result);
         };
         result
     }};
+    ($f:expr, $count:expr) => {
+        retry!($f, $count, false, |_, _| {})
+    };
     ($f:expr) => {
         retry!($f, 5)
     };
@@ -28,121 +53,576 @@ macro_rules! retry {
 pub enum Error {
     #[error("Mangalib error: {0}")]
     Mangalib(#[from] mangalib::Error),
+    #[error("Failed to scrap chapter {chapter:?}: {source}")]
+    ChapterFailed {
+        chapter: mangalib::MangaChapter,
+        source: mangalib::Error,
+    },
     #[error("Chapter not found")]
     ChapterNotFound { chapter: mangalib::MangaChapter },
     #[error("Chapter not found for filter, {dto:?}")]
-    ChapterNotFoundForFilter { dto: MangaScrappingParamsDto },
-    #[error("Can't get mutex lock")]
-    MutexLock,
+    ChapterNotFoundForFilter { dto: Box<MangaScrappingParamsDto> },
     #[error("Semaphore acquire error: {0}")]
     SemaphoreAcquire(#[from] AcquireError),
     #[error("Handle error")]
     Handle,
+    #[error("Callback error: {0}")]
+    Callback(#[from] callback::Error),
+    #[error("Callback url is required")]
+    MissingCallbackUrl,
+    #[error("Job was cancelled")]
+    Cancelled,
+    #[error("Job was cancelled on request")]
+    CancelRequested,
+    #[error("Job was not finished in {} seconds", .0.as_secs())]
+    TimedOut(Duration),
+    #[error("{}", .0.message)]
+    Coalesced(coalesce::Failure),
+}
+
+impl Error {
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Mangalib(_) => "mangalib",
+            Self::ChapterFailed { .. } => "chapter_failed",
+            Self::ChapterNotFound { .. } => "chapter_not_found",
+            Self::ChapterNotFoundForFilter { .. } => "chapter_not_found_for_filter",
+            Self::SemaphoreAcquire(_) => "semaphore_acquire",
+            Self::Handle => "handle",
+            Self::Callback(_) => "callback",
+            Self::MissingCallbackUrl => "missing_callback_url",
+            Self::Cancelled => "cancelled",
+            Self::CancelRequested => "cancel_requested",
+            Self::TimedOut(_) => "timed_out",
+            Self::Coalesced(failure) => failure.kind,
+        }
+    }
+
+    /// Whether the same request may succeed later
+    pub const fn is_retryable(&self) -> bool {
+        match self {
+            Self::ChapterNotFound { .. }
+            | Self::ChapterNotFoundForFilter { .. }
+            | Self::MissingCallbackUrl
+            | Self::CancelRequested => false,
+            Self::Coalesced(failure) => failure.retryable,
+            _ => true,
+        }
+    }
+
+    fn failed_chapters(&self) -> Vec<FailedChapter> {
+        match self {
+            Self::ChapterFailed { chapter, source } => vec![FailedChapter::new(chapter, source)],
+            Self::Coalesced(failure) => failure.failed_chapters.clone(),
+            _ => vec![],
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct ScrapMangaRequest {
     slug: String,
-    callback_url: String,
-    after_chapter: Option<String>,
-    after_volume: Option<String>,
+    /// Required unless manga is scrapped synchronously
+    callback_url: Option<String>,
+    #[serde(flatten)]
+    filter: ChapterFilter,
+    /// Send successfully scrapped chapters even if some of them failed
+    #[serde(default)]
+    allow_partial: bool,
+    #[serde(default)]
+    delivery: Delivery,
+    /// Overrides global `CALLBACK_SECRET` used to sign callback requests
+    #[serde(skip_serializing)]
+    callback_secret: Option<String>,
+    /// Where failures are reported, `callback_url` by default
+    error_callback_url: Option<String>,
+    /// Repeated request with the same key gets the job created for the first one
+    idempotency_key: Option<String>,
+    /// Job is cancelled when scrapping takes longer, capped by `--max-deadline`
+    deadline_seconds: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
 pub struct MangaScrappingParamsDto {
     pub slug: String,
-    pub after_chapter: Option<String>,
-    pub after_volume: Option<String>,
+    pub filter: ChapterFilter,
+    pub allow_partial: bool,
+    /// Not a part of the coalescing key, each job keeps its own deadline
+    pub deadline: Duration,
 }
 
-pub async fn process(chrome_max_count: u16, payload: ScrapMangaRequest) -> Result<(), Error> {
-    let dto = MangaScrappingParamsDto {
-        slug: payload.slug,
-        after_chapter: payload.after_chapter,
-        after_volume: payload.after_volume,
+impl MangaScrappingParamsDto {
+    /// Requests with the same key get the same `PublishedManga`
+    fn coalescing_key(&self) -> String {
+        format!(
+            "{}\n{}\n{}",
+            self.slug,
+            serde_json::to_string(&self.filter).unwrap_or_default(),
+            self.allow_partial
+        )
+    }
+}
+
+impl ScrapMangaRequest {
+    pub fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref()
+    }
+
+    fn dto(&self, max_deadline: Duration) -> MangaScrappingParamsDto {
+        MangaScrappingParamsDto {
+            slug: self.slug.clone(),
+            filter: self.filter.clone(),
+            allow_partial: self.allow_partial,
+            deadline: self
+                .deadline_seconds
+                .map_or(max_deadline, Duration::from_secs)
+                .min(max_deadline),
+        }
+    }
+}
+
+/// `browsers` is shared by all jobs of the process, one permit per Chrome instance.
+/// Scrapping is bounded by the deadline, each callback request by [`callback::REQUEST_TIMEOUT`]
+pub async fn process(
+    browsers: &Arc<Semaphore>,
+    payload: ScrapMangaRequest,
+    job: &JobTracker,
+    max_deadline: Duration,
+) -> Result<(), Error> {
+    info!(job_id = job.id(), manga_slug = payload.slug, "Job started");
+    start(job);
+    let dto = payload.dto(max_deadline);
+    let Some(callback_url) = payload.callback_url.as_deref() else {
+        let err = Error::MissingCallbackUrl;
+        fail(job, &err);
+        return Err(err);
+    };
+    let callback = Callback::new(callback_url, payload.callback_secret.clone());
+
+    let result = match payload.delivery {
+        Delivery::Single => process_single(browsers, &dto, &callback, job).await,
+        Delivery::Stream { batch_size } => {
+            process_stream(browsers, &dto, callback, batch_size, job).await
+        }
     };
-    let manga = get_manga_urls(&dto, chrome_max_count).await?;
-    info!(callback_url = payload.callback_url, "Sending manga",);
-    let response = send_info_about_manga(&payload.callback_url, &manga).await;
-    match response {
-        Ok(body) => info!(body = body, "Successfully sent manga"),
-        Err(err) => error!(manga_slug = dto.slug, "Error while sending manga: {err:?}"),
+
+    finish(job, &result);
+
+    match &result {
+        Err(Error::CancelRequested)
+            if job.cancel_reason() == Some(Reason::Requested { callback: false }) => {}
+        Err(err) => {
+            let error_callback = Callback::new(
+                payload
+                    .error_callback_url
+                    .as_deref()
+                    .unwrap_or(callback_url),
+                payload.callback_secret,
+            );
+            send_failure(&error_callback, &dto.slug, err).await;
+        }
+        Ok(_) => {}
     }
 
-    Ok(())
+    result.map(|_| ())
 }
 
-async fn get_manga_urls(
+/// Scraps manga without any callbacks
+pub async fn process_sync(
+    browsers: &Arc<Semaphore>,
+    payload: ScrapMangaRequest,
+    job: &JobTracker,
+    max_deadline: Duration,
+) -> Result<PublishedManga, Error> {
+    info!(
+        job_id = job.id(),
+        manga_slug = payload.slug,
+        "Sync job started"
+    );
+    start(job);
+    let result = scrap(&payload.dto(max_deadline), browsers, job, None).await;
+    finish(job, &result);
+
+    result
+}
+
+fn start(job: &JobTracker) {
+    job.start();
+    metrics::JOBS_STARTED.inc();
+}
+
+fn finish(job: &JobTracker, result: &Result<PublishedManga, Error>) {
+    match result {
+        Ok(manga) => {
+            job.succeed(manga);
+            metrics::JOBS_SUCCEEDED.inc();
+        }
+        Err(err) => fail(job, err),
+    }
+}
+
+fn fail(job: &JobTracker, err: &Error) {
+    job.fail(&err.to_string());
+    metrics::JOBS_FAILED.with_label_values(&[err.kind()]).inc();
+}
+
+async fn process_single(
+    browsers: &Arc<Semaphore>,
     dto: &MangaScrappingParamsDto,
-    chrome_max_count: u16,
+    callback: &Callback,
+    job: &JobTracker,
 ) -> Result<PublishedManga, Error> {
-    let chapter_urls_map: Arc<Mutex<HashMap<mangalib::MangaChapter, Vec<String>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
-    let chapters = mangalib::HeadlessBrowserClient::builder()
-        .build()
-        .get_manga_chapters(&dto.slug)?;
-    let chapters = match filter_chapters(chapters, dto) {
-        None => return Err(Error::ChapterNotFoundForFilter { dto: dto.clone() }),
-        Some(c) => c,
-    };
-    let semaphore = Arc::new(Semaphore::new(chrome_max_count as usize));
+    let manga = scrap(dto, browsers, job, None).await?;
+    info!(callback_url = callback.url(), "Sending manga",);
+    let sent = callback.send(&manga).await?;
+    job.event(JobEvent::CallbackSent {
+        url: callback.url().to_string(),
+        delivered: matches!(sent, Sent::Delivered(_)),
+    });
+    match sent {
+        Sent::Delivered(body) => info!(body = body, "Successfully sent manga"),
+        Sent::Queued(id) => error!(
+            manga_slug = dto.slug,
+            delivery_id = id,
+            "Error while sending manga, queued for redelivery"
+        ),
+    }
 
-    let mut handles = Vec::new();
-    let chapters_len = chapters.len();
-    for (index, chapter) in chapters.iter().enumerate() {
-        let urls = Arc::clone(&chapter_urls_map);
-        let slug = dto.slug.to_string();
-        let semaphore = semaphore.clone();
-        let chapter = chapter.clone();
-        handles.push(tokio::spawn(async move {
-            let _permit = semaphore.acquire().await?;
-            let result = retry!(
-                mangalib::HeadlessBrowserClient::builder()
-                    .build()
-                    .get_manga_chapter_images(&slug, &chapter, index + 1, chapters_len)
-            )?;
-            urls.lock()
-                .map_err(|_| Error::MutexLock)?
-                .insert(chapter, result);
-            Ok::<(), Error>(())
-        }));
+    Ok(manga)
+}
+
+async fn process_stream(
+    browsers: &Arc<Semaphore>,
+    dto: &MangaScrappingParamsDto,
+    callback: Callback,
+    batch_size: usize,
+    job: &JobTracker,
+) -> Result<PublishedManga, Error> {
+    info!(callback_url = callback.url(), "Streaming manga");
+    let (sender, receiver) = unbounded_channel();
+    let stream =
+        callback::stream_chapters(callback.clone(), dto.slug.clone(), batch_size, receiver);
+    let manga = scrap(dto, browsers, job, Some(sender)).await;
+    let sequence = stream.await.map_err(|_| Error::Handle)?;
+    let manga = manga?;
+
+    let sent = callback::send_stream_completed(&callback, sequence, &manga).await?;
+    job.event(JobEvent::CallbackSent {
+        url: callback.url().to_string(),
+        delivered: matches!(sent, Sent::Delivered(_)),
+    });
+
+    Ok(manga)
+}
+
+/// Identical requests running at once share one scrapping, each of them with its own callbacks
+async fn scrap(
+    dto: &MangaScrappingParamsDto,
+    browsers: &Arc<Semaphore>,
+    job: &JobTracker,
+    chapter_sender: Option<UnboundedSender<PublishedMangaChapter>>,
+) -> Result<PublishedManga, Error> {
+    let deadline = Instant::now() + dto.deadline;
+    let result = scrap_coalesced(dto, browsers, job, deadline, chapter_sender).await;
+    match (result, job.cancel_reason()) {
+        (Err(Error::Cancelled), Some(Reason::Requested { .. })) => Err(Error::CancelRequested),
+        (Err(Error::Cancelled), Some(Reason::TimedOut)) => Err(Error::TimedOut(dto.deadline)),
+        (result, _) => result,
     }
+}
 
-    for handle in handles {
-        handle.await.map_err(|_| Error::Handle)??; // Двойной `?` для JoinError и вашей Error
+/// Stalled browsers are left behind, they are not awaited after the deadline
+async fn until_deadline<T, F: Future<Output = Result<T, Error>>>(
+    job: &JobTracker,
+    deadline: Instant,
+    future: F,
+) -> Result<T, Error> {
+    match tokio::time::timeout_at(deadline, future).await {
+        Ok(result) => result,
+        Err(_) => {
+            job.cancel(Reason::TimedOut);
+            Err(Error::Cancelled)
+        }
     }
+}
+
+async fn scrap_coalesced(
+    dto: &MangaScrappingParamsDto,
+    browsers: &Arc<Semaphore>,
+    job: &JobTracker,
+    deadline: Instant,
+    chapter_sender: Option<UnboundedSender<PublishedMangaChapter>>,
+) -> Result<PublishedManga, Error> {
+    loop {
+        let follower = match coalesce::join(&dto.coalescing_key(), job.id()) {
+            coalesce::Role::Leader(leader) => {
+                let result = until_deadline(
+                    job,
+                    deadline,
+                    get_manga_urls(dto, browsers, job, chapter_sender),
+                )
+                .await;
+                leader.finish(&result);
+                return result;
+            }
+            coalesce::Role::Follower(follower) => follower,
+        };
 
-    let chapter_urls_map = chapter_urls_map
-        .lock()
-        .map_err(|_| Error::MutexLock)?
-        .clone();
+        info!(
+            job_id = job.id(),
+            leader_job_id = follower.leader_job_id,
+            "Job is attached to identical running job"
+        );
+        let result = tokio::select! {
+            result = until_deadline(job, deadline, follower.wait()) => result,
+            () = job.cancelled() => return Err(Error::Cancelled),
+        };
+        let manga = match result {
+            // Job of the leader was cancelled, this one is not
+            Err(Error::Coalesced(failure)) if failure.kind == Error::Cancelled.kind() => continue,
+            result => result?,
+        };
+        let chapters_total = manga.chapters.len() + manga.failed_chapters.len();
+        job.progress(chapters_total, chapters_total);
+        if let Some(sender) = chapter_sender {
+            for chapter in &manga.chapters {
+                let _ = sender.send(chapter.clone());
+            }
+        }
 
-    prepare_manga_for_publish(&dto.slug, &chapters, &chapter_urls_map)
+        return Ok(manga);
+    }
 }
 
-fn filter_chapters(
-    chapters: Vec<mangalib::MangaChapter>,
+type ChapterResult = (mangalib::MangaChapter, Result<Vec<String>, mangalib::Error>);
+type ChapterUrls = (
+    HashMap<mangalib::MangaChapter, Vec<String>>,
+    Vec<FailedChapter>,
+);
+
+/// Every scrapped chapter is also sent to `chapter_sender` as soon as it is ready
+async fn get_manga_urls(
     dto: &MangaScrappingParamsDto,
-) -> Option<Vec<mangalib::MangaChapter>> {
-    let (chapter_num, volume_num) = match (&dto.after_chapter, &dto.after_volume) {
-        (Some(c), Some(v)) => (c.to_string(), v.to_string()),
-        _ => return Some(chapters),
-    };
+    browsers: &Arc<Semaphore>,
+    job: &JobTracker,
+    chapter_sender: Option<UnboundedSender<PublishedMangaChapter>>,
+) -> Result<PublishedManga, Error> {
+    let tabs = Tabs::default();
+    let _closing = close_tabs_on_cancel(job, &tabs);
+    let chapters = fetch_chapters(&dto.slug, browsers, job, &tabs).await?;
+    let (chapters, missing_chapters) = selection::normalize(&dto.slug, chapters);
+    let chapters = dto
+        .filter
+        .apply(chapters)
+        .ok_or_else(|| Error::ChapterNotFoundForFilter {
+            dto: Box::new(dto.clone()),
+        })?;
 
-    let position = chapters.iter().position(|chapter| {
-        chapter_num.eq(&chapter.chapter_number) && volume_num.eq(&chapter.chapter_volume)
+    let chapters_len = chapters.len();
+    job.event(JobEvent::ChaptersFetched {
+        chapters_total: chapters_len,
+        missing_chapters: missing_chapters.len(),
     });
+    let mut handles: FuturesUnordered<_> = chapters
+        .iter()
+        .enumerate()
+        .map(|(index, chapter)| {
+            tokio::spawn(scrap_chapter(
+                dto.slug.clone(),
+                chapter.clone(),
+                index,
+                chapters_len,
+                browsers.clone(),
+                job.clone(),
+                tabs.clone(),
+            ))
+        })
+        .collect();
+
+    let result = collect_chapters(dto, job, &mut handles, chapter_sender).await;
+    if result.is_err() {
+        // Chapters left are stopped and awaited, so they neither take browsers nor outlive the job
+        job.stop();
+        while handles.next().await.is_some() {}
+    }
+    let (chapter_urls_map, failed_chapters) = result?;
 
-    position.map(|index| chapters.into_iter().skip(index + 1).collect())
+    let mut manga =
+        prepare_manga_for_publish(&dto.slug, &chapters, &chapter_urls_map, failed_chapters)?;
+    manga.missing_chapters = missing_chapters;
+
+    Ok(manga)
+}
+
+/// Cancelled job does not wait for pages open in a browser, its tabs are closed.
+/// Watching ends when the returned handle is dropped
+fn close_tabs_on_cancel(job: &JobTracker, tabs: &Tabs) -> AbortOnDropHandle<()> {
+    let (job, tabs) = (job.clone(), tabs.clone());
+    AbortOnDropHandle::new(tokio::spawn(async move {
+        job.cancelled().await;
+        // Closing waits for Chrome to answer
+        let _ = tokio::task::spawn_blocking(move || tabs.close()).await;
+    }))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+async fn fetch_chapters(
+    slug: &str,
+    browsers: &Arc<Semaphore>,
+    job: &JobTracker,
+    tabs: &Tabs,
+) -> Result<Vec<mangalib::MangaChapter>, Error> {
+    let _permit = browsers.acquire().await?;
+    if job.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+    let slug = slug.to_string();
+    let tabs = tabs.clone();
+    // Browser calls are blocking, a stalled one must not hold the runtime up
+    Ok(tokio::task::spawn_blocking(move || {
+        mangalib::HeadlessBrowserClient::builder()
+            .tabs(&tabs)
+            .build()
+            .get_manga_chapters(&slug)
+    })
+    .await
+    .map_err(|_| Error::Handle)??)
+}
+
+async fn scrap_chapter(
+    slug: String,
+    chapter: mangalib::MangaChapter,
+    index: usize,
+    chapters_len: usize,
+    semaphore: Arc<Semaphore>,
+    job: JobTracker,
+    tabs: Tabs,
+) -> Result<ChapterResult, Error> {
+    let _permit = semaphore.acquire().await?;
+    if job.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+    metrics::ACTIVE_PERMITS.inc();
+    let result = {
+        let chapter = chapter.clone();
+        let job = job.clone();
+        tokio::task::spawn_blocking(move || {
+            retry!(
+                mangalib::HeadlessBrowserClient::builder()
+                    .tabs(&tabs)
+                    .build()
+                    .get_manga_chapter_images(&slug, &chapter, index + 1, chapters_len),
+                5,
+                job.is_cancelled(),
+                |attempt, result: &Result<_, mangalib::Error>| {
+                    if let Err(err) = result {
+                        job.event(JobEvent::Retry {
+                            chapter: chapter.chapter_number.clone(),
+                            volume: chapter.chapter_volume.clone(),
+                            attempt,
+                            error: err.to_string(),
+                        });
+                    }
+                }
+            )
+        })
+        .await
+    };
+    metrics::ACTIVE_PERMITS.dec();
+    let result = result.map_err(|_| Error::Handle)?;
+    // Failure of the last attempt may be caused by cancellation itself
+    if result.is_err() && job.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+    Ok((chapter, result))
+}
+
+async fn collect_chapters(
+    dto: &MangaScrappingParamsDto,
+    job: &JobTracker,
+    handles: &mut FuturesUnordered<JoinHandle<Result<ChapterResult, Error>>>,
+    chapter_sender: Option<UnboundedSender<PublishedMangaChapter>>,
+) -> Result<ChapterUrls, Error> {
+    let chapters_len = handles.len();
+    let mut chapter_urls_map = HashMap::new();
+    let mut failed_chapters = Vec::new();
+    let mut chapters_done = 0;
+    let mut cancelled = false;
+    job.progress(chapters_done, chapters_len);
+    while let Some(handle) = handles.next().await {
+        let (chapter, result) = match handle.map_err(|_| Error::Handle)? {
+            Ok(chapter) => chapter,
+            // Remaining chapters are awaited, so no browser outlives the job
+            Err(Error::Cancelled) => {
+                cancelled = true;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        chapters_done += 1;
+        job.progress(chapters_done, chapters_len);
+        match result {
+            Ok(urls) => {
+                metrics::CHAPTERS_FETCHED.inc();
+                metrics::IMAGE_URLS_COLLECTED.inc_by(urls.len() as u64);
+                job.event(JobEvent::ChapterResolved {
+                    chapter: chapter.chapter_number.clone(),
+                    volume: chapter.chapter_volume.clone(),
+                    images_count: urls.len(),
+                    chapters_done,
+                    chapters_total: chapters_len,
+                });
+                if let Some(sender) = &chapter_sender {
+                    // Receiver is gone only if streaming task panicked, handled by the caller
+                    let _ = sender.send(PublishedMangaChapter::new(&chapter, urls.clone()));
+                }
+                chapter_urls_map.insert(chapter, urls);
+            }
+            Err(err) => {
+                job.event(JobEvent::ChapterFailed {
+                    chapter: chapter.chapter_number.clone(),
+                    volume: chapter.chapter_volume.clone(),
+                    reason: err.to_string(),
+                });
+                if !dto.allow_partial {
+                    return Err(Error::ChapterFailed {
+                        chapter,
+                        source: err,
+                    });
+                }
+                error!(
+                    manga_slug = dto.slug,
+                    chapter = chapter.chapter_number,
+                    volume = chapter.chapter_volume,
+                    "Failed to scrap chapter: {err:?}"
+                );
+                failed_chapters.push(FailedChapter::new(&chapter, &err));
+            }
+        }
+    }
+
+    if cancelled {
+        return Err(Error::Cancelled);
+    }
+
+    Ok((chapter_urls_map, failed_chapters))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PublishedManga {
     pub slug: String,
     pub chapters: Vec<PublishedMangaChapter>,
+    #[serde(default)]
+    pub failed_chapters: Vec<FailedChapter>,
+    /// Gaps in chapter numbers on mangalib, e.g. `37` between `36` and `38`, `40-45` between `39` and `46`
+    #[serde(default)]
+    pub missing_chapters: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PublishedMangaChapter {
     pub url: Option<String>,
     pub chapter: String,
@@ -150,39 +630,102 @@ pub struct PublishedMangaChapter {
     pub images_urls: Vec<String>,
 }
 
+impl PublishedMangaChapter {
+    fn new(chapter: &mangalib::MangaChapter, images_urls: Vec<String>) -> Self {
+        Self {
+            url: None,
+            chapter: chapter.chapter_number.clone(),
+            volume: chapter.chapter_volume.clone(),
+            images_urls,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FailedManga {
+    pub slug: String,
+    /// `failed`, or `cancelled` for jobs cancelled on request; tells this document apart from `PublishedManga`
+    pub status: String,
+    pub error_kind: String,
+    pub message: String,
+    pub failed_chapters: Vec<FailedChapter>,
+    pub retryable: bool,
+}
+
+impl FailedManga {
+    fn new(slug: &str, err: &Error) -> Self {
+        Self {
+            slug: slug.to_string(),
+            status: match err {
+                Error::CancelRequested => "cancelled",
+                _ => "failed",
+            }
+            .to_string(),
+            error_kind: err.kind().to_string(),
+            message: err.to_string(),
+            failed_chapters: err.failed_chapters(),
+            retryable: err.is_retryable(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FailedChapter {
+    pub chapter: String,
+    pub volume: String,
+    pub reason: String,
+}
+
+impl FailedChapter {
+    fn new(chapter: &mangalib::MangaChapter, err: &mangalib::Error) -> Self {
+        Self {
+            chapter: chapter.chapter_number.clone(),
+            volume: chapter.chapter_volume.clone(),
+            reason: err.to_string(),
+        }
+    }
+
+    fn matches(&self, chapter: &mangalib::MangaChapter) -> bool {
+        self.chapter == chapter.chapter_number && self.volume == chapter.chapter_volume
+    }
+}
+
 fn prepare_manga_for_publish(
     slug: &str,
     chapters: &[mangalib::MangaChapter],
     chapter_urls_map: &HashMap<mangalib::MangaChapter, Vec<String>>,
+    failed_chapters: Vec<FailedChapter>,
 ) -> Result<PublishedManga, Error> {
     let mut telegraph_urls: Vec<PublishedMangaChapter> = vec![];
     for chapter in chapters {
         let Some(url_images) = chapter_urls_map.get(chapter) else {
+            if failed_chapters.iter().any(|failed| failed.matches(chapter)) {
+                continue;
+            }
+
             return Err(Error::ChapterNotFound {
                 chapter: chapter.clone(),
             });
         };
 
-        telegraph_urls.push(PublishedMangaChapter {
-            url: None,
-            chapter: chapter.chapter_number.clone(),
-            volume: chapter.chapter_volume.clone(),
-            images_urls: url_images.clone(),
-        });
+        telegraph_urls.push(PublishedMangaChapter::new(chapter, url_images.clone()));
     }
 
     Ok(PublishedManga {
         slug: slug.to_string(),
         chapters: telegraph_urls,
+        failed_chapters,
+        missing_chapters: vec![],
     })
 }
 
-async fn send_info_about_manga(url: &str, manga: &PublishedManga) -> reqwest::Result<String> {
-    reqwest::Client::new()
-        .post(url)
-        .json(manga)
-        .send()
-        .await?
-        .text()
-        .await
+async fn send_failure(callback: &Callback, slug: &str, err: &Error) {
+    info!(callback_url = callback.url(), "Sending manga failure");
+    match callback.send(&FailedManga::new(slug, err)).await {
+        Ok(sent) => info!(sent = ?sent, "Manga failure sent"),
+        Err(err) => error!(
+            manga_slug = slug,
+            "Error while sending manga failure: {err:?}"
+        ),
+    }
 }
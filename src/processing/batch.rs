@@ -0,0 +1,155 @@
+use super::selection::ChapterFilter;
+use super::{Error, ScrapMangaRequest, fail, process};
+use crate::callback::{Callback, Delivery, Sent};
+use crate::jobs::{JobStatus, JobTracker};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+/// Slugs scrapped one after another with shared callbacks, so they do not compete for browsers
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ScrapMangaBatchRequest {
+    pub(super) items: Vec<BatchItem>,
+    pub(super) callback_url: Option<String>,
+    #[serde(default)]
+    pub(super) allow_partial: bool,
+    #[serde(default)]
+    pub(super) delivery: Delivery,
+    #[serde(skip_serializing)]
+    pub(super) callback_secret: Option<String>,
+    pub(super) error_callback_url: Option<String>,
+    /// Deadline of every slug, not of the whole batch
+    pub(super) deadline_seconds: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct BatchItem {
+    slug: String,
+    #[serde(flatten)]
+    filter: ChapterFilter,
+}
+
+impl ScrapMangaBatchRequest {
+    /// Same requests as if every slug was sent separately
+    pub fn requests(&self) -> Vec<ScrapMangaRequest> {
+        self.items
+            .iter()
+            .map(|item| ScrapMangaRequest {
+                slug: item.slug.clone(),
+                callback_url: self.callback_url.clone(),
+                filter: item.filter.clone(),
+                allow_partial: self.allow_partial,
+                delivery: self.delivery,
+                callback_secret: self.callback_secret.clone(),
+                error_callback_url: self.error_callback_url.clone(),
+                idempotency_key: None,
+                deadline_seconds: self.deadline_seconds,
+            })
+            .collect()
+    }
+}
+
+/// Sent to `callback_url` after results of all slugs
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchCompleted {
+    /// Always `batch_completed`, tells this document apart from `PublishedManga`
+    pub status: String,
+    pub batch_id: String,
+    pub items: Vec<BatchItemResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchItemResult {
+    pub slug: String,
+    pub job_id: String,
+    /// `succeeded`, `failed` or `cancelled`
+    pub status: JobStatus,
+    pub error_kind: Option<String>,
+    pub message: Option<String>,
+}
+
+impl BatchItemResult {
+    fn new(slug: &str, job: &JobTracker, result: &Result<(), Error>) -> Self {
+        let (status, error_kind, message) = match result {
+            Ok(()) => (JobStatus::Succeeded, None, None),
+            Err(err) => (
+                match err {
+                    Error::CancelRequested => JobStatus::Cancelled,
+                    _ => JobStatus::Failed,
+                },
+                Some(err.kind().to_string()),
+                Some(err.to_string()),
+            ),
+        };
+
+        Self {
+            slug: slug.to_string(),
+            job_id: job.id().to_string(),
+            status,
+            error_kind,
+            message,
+        }
+    }
+}
+
+/// `jobs` are created for `batch.requests()` in the same order, each slug gets its own callback
+pub async fn process_batch(
+    browsers: &Arc<Semaphore>,
+    batch_id: &str,
+    batch: ScrapMangaBatchRequest,
+    jobs: Vec<JobTracker>,
+    max_deadline: Duration,
+) -> Result<BatchCompleted, Error> {
+    info!(
+        batch_id = batch_id,
+        items_count = jobs.len(),
+        "Batch started"
+    );
+    let Some(callback_url) = batch.callback_url.as_deref() else {
+        return Err(Error::MissingCallbackUrl);
+    };
+    let callback = Callback::new(callback_url, batch.callback_secret.clone());
+
+    let mut items = Vec::with_capacity(jobs.len());
+    let mut requests = batch.requests().into_iter().zip(jobs);
+    while let Some((request, job)) = requests.next() {
+        let slug = request.slug.clone();
+        let result = process(browsers, request, &job, max_deadline).await;
+        if let Err(Error::Cancelled) = result {
+            // Not started jobs would only report the same
+            for (_, job) in requests {
+                fail(&job, &Error::Cancelled);
+            }
+            return Err(Error::Cancelled);
+        }
+        if let Err(err) = &result {
+            error!(
+                batch_id = batch_id,
+                manga_slug = slug,
+                "Batch item failed: {err:?}"
+            );
+        }
+        items.push(BatchItemResult::new(&slug, &job, &result));
+    }
+
+    let completed = BatchCompleted {
+        status: "batch_completed".to_string(),
+        batch_id: batch_id.to_string(),
+        items,
+    };
+    match callback.send(&completed).await? {
+        Sent::Delivered(body) => info!(batch_id = batch_id, body = body, "Batch completion sent"),
+        Sent::Queued(id) => error!(
+            batch_id = batch_id,
+            delivery_id = id,
+            "Error while sending batch completion, queued for redelivery"
+        ),
+    }
+
+    Ok(completed)
+}
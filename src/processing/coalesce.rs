@@ -0,0 +1,214 @@
+use super::{Error, FailedChapter, PublishedManga};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+type Outcome = Option<Result<PublishedManga, Failure>>;
+
+static RUNNING: Lazy<Mutex<HashMap<String, Running>>> = Lazy::new(Mutex::default);
+
+struct Running {
+    job_id: String,
+    sender: watch::Sender<Outcome>,
+}
+
+/// Error of the scrapping shared with attached jobs
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub kind: &'static str,
+    pub message: String,
+    pub retryable: bool,
+    pub failed_chapters: Vec<FailedChapter>,
+}
+
+pub enum Role {
+    Leader(Leader),
+    Follower(Follower),
+}
+
+/// Scraps for itself and for every job attached until it finishes
+pub struct Leader {
+    key: String,
+    sender: watch::Sender<Outcome>,
+}
+
+pub struct Follower {
+    pub leader_job_id: String,
+    receiver: watch::Receiver<Outcome>,
+}
+
+/// The first job with the key becomes the leader, identical jobs started before it finishes follow it
+pub fn join(key: &str, job_id: &str) -> Role {
+    let (sender, _) = watch::channel(None);
+    let Ok(mut running) = RUNNING.lock() else {
+        return Role::Leader(Leader {
+            key: key.to_string(),
+            sender,
+        });
+    };
+
+    if let Some(leader) = running.get(key) {
+        return Role::Follower(Follower {
+            leader_job_id: leader.job_id.clone(),
+            receiver: leader.sender.subscribe(),
+        });
+    }
+    running.insert(
+        key.to_string(),
+        Running {
+            job_id: job_id.to_string(),
+            sender: sender.clone(),
+        },
+    );
+
+    Role::Leader(Leader {
+        key: key.to_string(),
+        sender,
+    })
+}
+
+impl Leader {
+    pub fn finish(self, result: &Result<PublishedManga, Error>) {
+        let outcome = match result {
+            Ok(manga) => Ok(manga.clone()),
+            Err(err) => Err(Failure {
+                kind: err.kind(),
+                message: err.to_string(),
+                retryable: err.is_retryable(),
+                failed_chapters: err.failed_chapters(),
+            }),
+        };
+        self.sender.send_replace(Some(outcome));
+    }
+}
+
+/// Jobs started after the leader is gone scrap again
+impl Drop for Leader {
+    fn drop(&mut self) {
+        let Ok(mut running) = RUNNING.lock() else {
+            return;
+        };
+        if running
+            .get(&self.key)
+            .is_some_and(|leader| leader.sender.same_channel(&self.sender))
+        {
+            running.remove(&self.key);
+        }
+    }
+}
+
+impl Follower {
+    pub async fn wait(mut self) -> Result<PublishedManga, Error> {
+        let outcome = match self.receiver.wait_for(Option::is_some).await {
+            Ok(outcome) => outcome.clone(),
+            // Leader is gone without result, e.g. it panicked
+            Err(_) => return Err(Error::Handle),
+        };
+
+        match outcome {
+            Some(Ok(manga)) => Ok(manga),
+            Some(Err(failure)) => Err(Error::Coalesced(failure)),
+            None => Err(Error::Handle),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> String {
+        format!("manga-{}", uuid::Uuid::new_v4())
+    }
+
+    fn manga() -> PublishedManga {
+        PublishedManga {
+            slug: "manga".to_string(),
+            chapters: vec![],
+            failed_chapters: vec![],
+            missing_chapters: vec![],
+        }
+    }
+
+    fn leader(role: Role) -> Leader {
+        match role {
+            Role::Leader(leader) => leader,
+            Role::Follower(_) => panic!("expected leader"),
+        }
+    }
+
+    fn follower(role: Role) -> Follower {
+        match role {
+            Role::Follower(follower) => follower,
+            Role::Leader(_) => panic!("expected follower"),
+        }
+    }
+
+    #[tokio::test]
+    async fn follower_gets_result_of_leader() {
+        let key = key();
+        let leader = leader(join(&key, "first"));
+        let follower = follower(join(&key, "second"));
+        assert_eq!(follower.leader_job_id, "first");
+
+        leader.finish(&Ok(manga()));
+        let manga = follower.wait().await.unwrap();
+        assert_eq!(manga.slug, "manga");
+    }
+
+    #[tokio::test]
+    async fn follower_gets_failure_of_leader() {
+        let key = key();
+        let leader = leader(join(&key, "first"));
+        let follower = follower(join(&key, "second"));
+
+        leader.finish(&Err(Error::Cancelled));
+        let Err(Error::Coalesced(failure)) = follower.wait().await else {
+            panic!("expected coalesced failure");
+        };
+        assert_eq!(failure.kind, Error::Cancelled.kind());
+        assert!(failure.retryable);
+    }
+
+    #[tokio::test]
+    async fn job_leads_again_after_cancelled_leader_is_gone() {
+        let key = key();
+        let leader = leader(join(&key, "first"));
+        let follower = follower(join(&key, "second"));
+
+        leader.finish(&Err(Error::Cancelled));
+        assert!(follower.wait().await.is_err());
+
+        let leader = self::leader(join(&key, "second"));
+        let follower = self::follower(join(&key, "third"));
+        assert_eq!(follower.leader_job_id, "second");
+        drop(leader);
+    }
+
+    #[tokio::test]
+    async fn follower_fails_when_leader_is_dropped_without_result() {
+        let key = key();
+        let leader = leader(join(&key, "first"));
+        let follower = follower(join(&key, "second"));
+
+        drop(leader);
+        assert!(matches!(follower.wait().await, Err(Error::Handle)));
+    }
+
+    #[test]
+    fn finished_leader_does_not_remove_next_leader() {
+        let key = key();
+        let first = leader(join(&key, "first"));
+        drop(first);
+        let second = leader(join(&key, "second"));
+        let stale = Leader {
+            key: key.clone(),
+            sender: watch::channel(None).0,
+        };
+        drop(stale);
+
+        assert_eq!(follower(join(&key, "third")).leader_job_id, "second");
+        drop(second);
+    }
+}
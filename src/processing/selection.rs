@@ -0,0 +1,399 @@
+use crate::mangalib::MangaChapter;
+use crate::mangalib::chapter_key::{ChapterKey, ChapterNumber};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use tracing::warn;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ChapterFilter {
+    /// Chapters strictly after this one, requires `after_volume`
+    pub after_chapter: Option<String>,
+    pub after_volume: Option<String>,
+    /// Inclusive lower bound, chapter alone compares by chapter number only
+    pub from_chapter: Option<String>,
+    pub from_volume: Option<String>,
+    /// Inclusive upper bound, chapter alone compares by chapter number only
+    pub to_chapter: Option<String>,
+    pub to_volume: Option<String>,
+    /// Scrap only these chapters, e.g. `failed_chapters` of a previous partial job,
+    /// can't be combined with other fields
+    pub chapters: Option<Vec<ChapterRef>>,
+    /// Only the last N chapters left after other filters
+    pub latest: Option<usize>,
+    /// Only chapters of these volumes
+    pub volumes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChapterRef {
+    pub chapter: String,
+    pub volume: String,
+}
+
+impl ChapterFilter {
+    /// `true` when the explicit chapters are combined with other fields
+    pub fn is_mixed(&self) -> bool {
+        self.chapters.is_some()
+            && (self.after_chapter.is_some()
+                || self.after_volume.is_some()
+                || self.from_chapter.is_some()
+                || self.from_volume.is_some()
+                || self.to_chapter.is_some()
+                || self.to_volume.is_some()
+                || self.latest.is_some()
+                || self.volumes.is_some())
+    }
+
+    /// `true` when no chapter can be both within `from_*` and `to_*` bounds.
+    /// Bound with a volume and bound by chapter number alone select different orders, so only their volumes are compared
+    pub fn is_inverted(&self) -> bool {
+        let from = Bound::new(self.from_volume.as_deref(), self.from_chapter.as_deref());
+        let to = Bound::new(self.to_volume.as_deref(), self.to_chapter.as_deref());
+        match (from, to) {
+            (Some(Bound::Chapter(from)), Some(Bound::Chapter(to))) => from > to,
+            (Some(Bound::Number(from)), Some(Bound::Number(to))) => from > to,
+            (Some(from), Some(to)) => from
+                .volume()
+                .zip(to.volume())
+                .is_some_and(|(from, to)| from > to),
+            _ => false,
+        }
+    }
+
+    /// Returns `None` when one of explicitly requested chapters does not exist
+    pub fn apply(&self, chapters: Vec<MangaChapter>) -> Option<Vec<MangaChapter>> {
+        if let Some(refs) = &self.chapters {
+            return refs
+                .iter()
+                .map(|chapter_ref| {
+                    let key = ChapterKey::new(&chapter_ref.volume, &chapter_ref.chapter);
+                    chapters
+                        .iter()
+                        .find(|chapter| chapter.key() == key)
+                        .cloned()
+                })
+                .collect();
+        }
+
+        let after = match (&self.after_volume, &self.after_chapter) {
+            (Some(volume), Some(chapter)) => Some(ChapterKey::new(volume, chapter)),
+            _ => None,
+        };
+        let from = Bound::new(self.from_volume.as_deref(), self.from_chapter.as_deref());
+        let to = Bound::new(self.to_volume.as_deref(), self.to_chapter.as_deref());
+        let volumes = self.volumes.as_ref().map(|volumes| {
+            volumes
+                .iter()
+                .map(|volume| ChapterNumber::parse_volume(volume))
+                .collect::<Vec<_>>()
+        });
+
+        let mut chapters: Vec<MangaChapter> = chapters
+            .into_iter()
+            .filter(|chapter| {
+                let key = chapter.key();
+
+                after.as_ref().is_none_or(|after| key > *after)
+                    && from
+                        .as_ref()
+                        .is_none_or(|from| from.compare(&key) != Ordering::Less)
+                    && to
+                        .as_ref()
+                        .is_none_or(|to| to.compare(&key) != Ordering::Greater)
+                    && volumes
+                        .as_ref()
+                        .is_none_or(|volumes| volumes.contains(key.volume()))
+            })
+            .collect();
+
+        if let Some(latest) = self.latest {
+            chapters = chapters.split_off(chapters.len().saturating_sub(latest));
+        }
+
+        Some(chapters)
+    }
+}
+
+enum Bound {
+    Chapter(ChapterKey),
+    Volume(ChapterNumber),
+    Number(ChapterNumber),
+}
+
+impl Bound {
+    fn new(volume: Option<&str>, chapter: Option<&str>) -> Option<Self> {
+        match (volume, chapter) {
+            (Some(volume), Some(chapter)) => Some(Self::Chapter(ChapterKey::new(volume, chapter))),
+            (Some(volume), None) => Some(Self::Volume(ChapterNumber::parse_volume(volume))),
+            (None, Some(chapter)) => Some(Self::Number(ChapterNumber::parse(chapter))),
+            (None, None) => None,
+        }
+    }
+
+    const fn volume(&self) -> Option<&ChapterNumber> {
+        match self {
+            Self::Chapter(bound) => Some(bound.volume()),
+            Self::Volume(volume) => Some(volume),
+            Self::Number(_) => None,
+        }
+    }
+
+    /// Ordering of the chapter relative to this bound
+    fn compare(&self, key: &ChapterKey) -> Ordering {
+        match self {
+            Self::Chapter(bound) => key.cmp(bound),
+            Self::Volume(volume) => key.volume().cmp(volume),
+            Self::Number(number) => key.number().cmp(number),
+        }
+    }
+}
+
+/// Sorts chapters by [`ChapterKey`], drops duplicates and returns gaps in chapter numbers,
+/// one entry per gap, so the list is never longer than the chapters
+pub fn normalize(slug: &str, mut chapters: Vec<MangaChapter>) -> (Vec<MangaChapter>, Vec<String>) {
+    chapters.sort_by_cached_key(MangaChapter::key);
+    chapters.dedup_by(|chapter, previous| {
+        let duplicate = chapter.key() == previous.key();
+        if duplicate {
+            warn!(
+                manga_slug = slug,
+                chapter = chapter.chapter_number,
+                volume = chapter.chapter_volume,
+                "Duplicate chapter skipped"
+            );
+        }
+
+        duplicate
+    });
+
+    let mut missing_chapters = Vec::new();
+    let numbers: Vec<u64> = chapters
+        .iter()
+        .filter_map(|chapter| chapter.key().number().whole())
+        .collect();
+    for pair in numbers.windows(2) {
+        let (first, last) = (pair[0].saturating_add(1), pair[1].saturating_sub(1));
+        match first.cmp(&last) {
+            Ordering::Less => missing_chapters.push(format!("{first}-{last}")),
+            Ordering::Equal => missing_chapters.push(first.to_string()),
+            Ordering::Greater => {}
+        }
+    }
+
+    if !missing_chapters.is_empty() {
+        warn!(
+            manga_slug = slug,
+            "Missing chapters: {}",
+            missing_chapters.join(", ")
+        );
+    }
+
+    (chapters, missing_chapters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapters(keys: &[(&str, &str)]) -> Vec<MangaChapter> {
+        keys.iter()
+            .map(|(volume, number)| MangaChapter::new(*volume, *number))
+            .collect()
+    }
+
+    fn numbers(chapters: &[MangaChapter]) -> Vec<&str> {
+        chapters
+            .iter()
+            .map(|chapter| chapter.chapter_number.as_str())
+            .collect()
+    }
+
+    fn all() -> Vec<MangaChapter> {
+        chapters(&[
+            ("1", "1"),
+            ("1", "2"),
+            ("1", "2.5"),
+            ("2", "3"),
+            ("2", "4"),
+            ("3", "5"),
+        ])
+    }
+
+    #[test]
+    fn empty_filter_keeps_all_chapters() {
+        let selected = ChapterFilter::default().apply(all()).unwrap();
+
+        assert_eq!(numbers(&selected), ["1", "2", "2.5", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn after_is_exclusive() {
+        let filter = ChapterFilter {
+            after_volume: Some("1".to_string()),
+            after_chapter: Some("2.5".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(numbers(&filter.apply(all()).unwrap()), ["3", "4", "5"]);
+    }
+
+    #[test]
+    fn from_and_to_are_inclusive() {
+        let filter = ChapterFilter {
+            from_chapter: Some("2".to_string()),
+            to_volume: Some("2".to_string()),
+            to_chapter: Some("3".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(numbers(&filter.apply(all()).unwrap()), ["2", "2.5", "3"]);
+    }
+
+    #[test]
+    fn volume_bounds_include_whole_volumes() {
+        let filter = ChapterFilter {
+            from_volume: Some("2".to_string()),
+            to_volume: Some("2".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(numbers(&filter.apply(all()).unwrap()), ["3", "4"]);
+    }
+
+    #[test]
+    fn latest_is_applied_after_other_filters() {
+        let filter = ChapterFilter {
+            volumes: Some(vec!["1".to_string(), "2".to_string()]),
+            latest: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(numbers(&filter.apply(all()).unwrap()), ["3", "4"]);
+    }
+
+    #[test]
+    fn explicit_chapters_are_kept_in_requested_order() {
+        let filter = ChapterFilter {
+            chapters: Some(vec![
+                ChapterRef {
+                    chapter: "4".to_string(),
+                    volume: "2".to_string(),
+                },
+                ChapterRef {
+                    chapter: "1".to_string(),
+                    volume: "1".to_string(),
+                },
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(numbers(&filter.apply(all()).unwrap()), ["4", "1"]);
+    }
+
+    #[test]
+    fn missing_explicit_chapter_fails_selection() {
+        let filter = ChapterFilter {
+            chapters: Some(vec![ChapterRef {
+                chapter: "4".to_string(),
+                volume: "1".to_string(),
+            }]),
+            ..Default::default()
+        };
+
+        assert!(filter.apply(all()).is_none());
+    }
+
+    #[test]
+    fn normalize_sorts_and_drops_duplicates() {
+        let (chapters, missing) = normalize(
+            "slug",
+            chapters(&[
+                ("2", "3"),
+                ("1", "1"),
+                ("1", "2"),
+                ("1", "2.0"),
+                ("1", "1 extra"),
+            ]),
+        );
+
+        assert_eq!(numbers(&chapters), ["1", "1 extra", "2", "3"]);
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn normalize_reports_gaps_as_ranges() {
+        let (_, missing) = normalize(
+            "slug",
+            chapters(&[
+                ("1", "1"),
+                ("1", "3"),
+                ("1", "3.5"),
+                ("2", "10"),
+                ("2", "11"),
+            ]),
+        );
+
+        assert_eq!(missing, ["2", "4-9"]);
+    }
+
+    fn bounds(
+        from: (Option<&str>, Option<&str>),
+        to: (Option<&str>, Option<&str>),
+    ) -> ChapterFilter {
+        ChapterFilter {
+            from_volume: from.0.map(str::to_string),
+            from_chapter: from.1.map(str::to_string),
+            to_volume: to.0.map(str::to_string),
+            to_chapter: to.1.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn detects_inverted_bounds() {
+        for (from, to) in [
+            ((None, Some("10")), (None, Some("5"))),
+            ((Some("2"), Some("1")), (Some("1"), Some("50"))),
+            ((Some("3"), None), (Some("2"), None)),
+            ((Some("2"), None), (Some("1"), Some("10"))),
+        ] {
+            assert!(bounds(from, to).is_inverted(), "{from:?} {to:?}");
+        }
+    }
+
+    #[test]
+    fn accepts_bounds_selecting_chapters() {
+        for (from, to) in [
+            ((None, Some("5")), (None, Some("5"))),
+            ((Some("1"), Some("5")), (Some("1"), Some("5"))),
+            ((Some("1"), None), (Some("1"), Some("2"))),
+            // Later volume may have chapters numbered from 1 again
+            ((Some("3"), Some("1")), (None, Some("2"))),
+            ((None, Some("10")), (None, None)),
+        ] {
+            assert!(!bounds(from, to).is_inverted(), "{from:?} {to:?}");
+        }
+    }
+
+    #[test]
+    fn explicit_chapters_are_not_mixed_with_other_fields() {
+        let chapters = Some(vec![ChapterRef {
+            chapter: "1".to_string(),
+            volume: "1".to_string(),
+        }]);
+        let explicit = ChapterFilter {
+            chapters: chapters.clone(),
+            ..Default::default()
+        };
+        let mixed = ChapterFilter {
+            chapters,
+            latest: Some(1),
+            ..Default::default()
+        };
+
+        assert!(!explicit.is_mixed());
+        assert!(mixed.is_mixed());
+        assert!(!ChapterFilter::default().is_mixed());
+    }
+}
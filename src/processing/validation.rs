@@ -0,0 +1,335 @@
+use crate::callback::Delivery;
+use crate::callback::policy::{self, CallbackPolicy};
+use crate::processing::ScrapMangaRequest;
+use crate::processing::batch::{MAX_BATCH_SIZE, ScrapMangaBatchRequest};
+use crate::processing::selection::ChapterFilter;
+use reqwest::Url;
+use thiserror::Error;
+
+const SLUG_MAX_LENGTH: usize = 255;
+const IDEMPOTENCY_KEY_MAX_LENGTH: usize = 255;
+
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("Slug must be 1-{SLUG_MAX_LENGTH} latin letters, digits, '-', '_' or '.'")]
+    BadSlug,
+    #[error("Field {0} is required")]
+    Required(&'static str),
+    #[error("Field {field} is not a valid url: {reason}")]
+    BadUrl { field: &'static str, reason: String },
+    #[error("Fields {0} and {1} must be set together")]
+    Unpaired(&'static str, &'static str),
+    #[error("Field {0} must be greater than zero")]
+    NotPositive(&'static str),
+    #[error("Field {0} must not be empty")]
+    Empty(&'static str),
+    #[error("Field chapters can't be combined with other chapter selection fields")]
+    MixedChapters,
+    #[error("Fields from_volume and from_chapter must not go after to_volume and to_chapter")]
+    InvertedRange,
+    #[error("Field {field} is not allowed: {source}")]
+    CallbackNotAllowed {
+        field: &'static str,
+        source: policy::Error,
+    },
+    #[error("Field idempotency_key must be 1-{IDEMPOTENCY_KEY_MAX_LENGTH} characters")]
+    BadIdempotencyKey,
+    #[error("Field items must have 1-{MAX_BATCH_SIZE} entries")]
+    BatchSize,
+    #[error("Item {index} is invalid: {source}")]
+    BatchItem {
+        index: usize,
+        source: Box<ValidationError>,
+    },
+}
+
+impl ScrapMangaRequest {
+    pub fn validate(&self, require_callback: bool) -> Result<(), ValidationError> {
+        validate_slug(&self.slug)?;
+
+        match &self.callback_url {
+            Some(url) => validate_url("callback_url", url)?,
+            None if require_callback => return Err(ValidationError::Required("callback_url")),
+            None => {}
+        }
+        if let Some(url) = &self.error_callback_url {
+            validate_url("error_callback_url", url)?;
+        }
+
+        validate_filter(&self.filter)?;
+        if self.deadline_seconds == Some(0) {
+            return Err(ValidationError::NotPositive("deadline_seconds"));
+        }
+        if let Delivery::Stream { batch_size: 0 } = self.delivery {
+            return Err(ValidationError::NotPositive("delivery.batch_size"));
+        }
+        if self
+            .idempotency_key
+            .as_ref()
+            .is_some_and(|key| key.is_empty() || key.chars().count() > IDEMPOTENCY_KEY_MAX_LENGTH)
+        {
+            return Err(ValidationError::BadIdempotencyKey);
+        }
+
+        Ok(())
+    }
+
+    /// Resolves callback hosts, so it is done once on acceptance rather than in `validate`
+    pub async fn check_callbacks(&self, policy: &CallbackPolicy) -> Result<(), ValidationError> {
+        check_callbacks(
+            policy,
+            self.callback_url.as_deref(),
+            self.error_callback_url.as_deref(),
+        )
+        .await
+    }
+}
+
+impl ScrapMangaBatchRequest {
+    /// Batch is always processed in background, so `callback_url` is required
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.items.is_empty() || self.items.len() > MAX_BATCH_SIZE {
+            return Err(ValidationError::BatchSize);
+        }
+
+        for (index, request) in self.requests().iter().enumerate() {
+            request
+                .validate(true)
+                .map_err(|source| ValidationError::BatchItem {
+                    index,
+                    source: Box::new(source),
+                })?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn check_callbacks(&self, policy: &CallbackPolicy) -> Result<(), ValidationError> {
+        check_callbacks(
+            policy,
+            self.callback_url.as_deref(),
+            self.error_callback_url.as_deref(),
+        )
+        .await
+    }
+}
+
+async fn check_callbacks(
+    policy: &CallbackPolicy,
+    callback_url: Option<&str>,
+    error_callback_url: Option<&str>,
+) -> Result<(), ValidationError> {
+    for (field, url) in [
+        ("callback_url", callback_url),
+        ("error_callback_url", error_callback_url),
+    ] {
+        if let Some(url) = url {
+            policy
+                .check(url)
+                .await
+                .map_err(|source| ValidationError::CallbackNotAllowed { field, source })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Filters which would select nothing or ignore some of their fields are rejected
+fn validate_filter(filter: &ChapterFilter) -> Result<(), ValidationError> {
+    if filter.after_chapter.is_some() != filter.after_volume.is_some() {
+        return Err(ValidationError::Unpaired("after_chapter", "after_volume"));
+    }
+    if filter.latest == Some(0) {
+        return Err(ValidationError::NotPositive("latest"));
+    }
+    if filter.volumes.as_ref().is_some_and(Vec::is_empty) {
+        return Err(ValidationError::Empty("volumes"));
+    }
+    if filter.chapters.as_ref().is_some_and(Vec::is_empty) {
+        return Err(ValidationError::Empty("chapters"));
+    }
+    if filter.is_mixed() {
+        return Err(ValidationError::MixedChapters);
+    }
+    if filter.is_inverted() {
+        return Err(ValidationError::InvertedRange);
+    }
+
+    Ok(())
+}
+
+pub fn validate_slug(slug: &str) -> Result<(), ValidationError> {
+    let is_valid = !slug.is_empty()
+        && slug.len() <= SLUG_MAX_LENGTH
+        && slug
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || "-_.".contains(char))
+        && !slug.starts_with('.');
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(ValidationError::BadSlug)
+    }
+}
+
+/// Allowed schemes are up to the callback policy
+fn validate_url(field: &'static str, url: &str) -> Result<(), ValidationError> {
+    let url = Url::parse(url).map_err(|err| ValidationError::BadUrl {
+        field,
+        reason: err.to_string(),
+    })?;
+
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(ValidationError::BadUrl {
+            field,
+            reason: "host is missing".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn request(body: Value) -> ScrapMangaRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn batch(body: Value) -> ScrapMangaBatchRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn accepts_valid_slugs() {
+        for slug in ["a", "one-piece", "7965--chainsaw-man", "manga_1.2"] {
+            assert!(validate_slug(slug).is_ok(), "{slug}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_slugs() {
+        let long = "a".repeat(SLUG_MAX_LENGTH + 1);
+        for slug in ["", ".hidden", "../etc", "a/b", "манга", "a b", &long] {
+            assert!(
+                matches!(validate_slug(slug), Err(ValidationError::BadSlug)),
+                "{slug}"
+            );
+        }
+    }
+
+    #[test]
+    fn urls_need_a_host() {
+        assert!(validate_url("callback_url", "https://example.com/hook").is_ok());
+        // Schemes are checked by the callback policy
+        assert!(validate_url("callback_url", "ftp://example.com").is_ok());
+        for url in ["example.com", "/hook", "file:///etc/passwd", "http://"] {
+            assert!(
+                matches!(
+                    validate_url("callback_url", url),
+                    Err(ValidationError::BadUrl {
+                        field: "callback_url",
+                        ..
+                    })
+                ),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn callback_url_is_required_only_for_background_jobs() {
+        let sync = request(json!({ "slug": "manga" }));
+
+        assert!(sync.validate(false).is_ok());
+        assert!(matches!(
+            sync.validate(true),
+            Err(ValidationError::Required("callback_url"))
+        ));
+    }
+
+    #[test]
+    fn after_fields_go_together() {
+        for body in [
+            json!({ "slug": "manga", "after_chapter": "5" }),
+            json!({ "slug": "manga", "after_volume": "1" }),
+        ] {
+            assert!(matches!(
+                request(body).validate(false),
+                Err(ValidationError::Unpaired("after_chapter", "after_volume"))
+            ));
+        }
+        let paired = json!({ "slug": "manga", "after_chapter": "5", "after_volume": "1" });
+        assert!(request(paired).validate(false).is_ok());
+    }
+
+    #[test]
+    fn rejects_zero_counts() {
+        for (body, field) in [
+            (json!({ "slug": "manga", "latest": 0 }), "latest"),
+            (
+                json!({ "slug": "manga", "deadline_seconds": 0 }),
+                "deadline_seconds",
+            ),
+            (
+                json!({ "slug": "manga", "delivery": { "mode": "stream", "batch_size": 0 } }),
+                "delivery.batch_size",
+            ),
+        ] {
+            assert!(matches!(
+                request(body).validate(false),
+                Err(ValidationError::NotPositive(name)) if name == field
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_idempotency_keys() {
+        let long = "k".repeat(IDEMPOTENCY_KEY_MAX_LENGTH + 1);
+        for key in ["", long.as_str()] {
+            assert!(matches!(
+                request(json!({ "slug": "manga", "idempotency_key": key })).validate(false),
+                Err(ValidationError::BadIdempotencyKey)
+            ));
+        }
+    }
+
+    #[test]
+    fn batch_needs_items_within_limit() {
+        let items: Vec<Value> = (0..=MAX_BATCH_SIZE)
+            .map(|_| json!({ "slug": "manga" }))
+            .collect();
+        for items in [vec![], items] {
+            let batch = batch(json!({ "items": items, "callback_url": "https://example.com" }));
+            assert!(matches!(batch.validate(), Err(ValidationError::BatchSize)));
+        }
+    }
+
+    #[test]
+    fn batch_error_points_to_the_item() {
+        let batch = batch(json!({
+            "items": [{ "slug": "first" }, { "slug": "second" }, { "slug": "" }],
+            "callback_url": "https://example.com"
+        }));
+
+        let Err(ValidationError::BatchItem { index, source }) = batch.validate() else {
+            panic!("item error expected");
+        };
+        assert_eq!(index, 2);
+        assert!(matches!(*source, ValidationError::BadSlug));
+    }
+
+    #[test]
+    fn batch_items_need_callback_url() {
+        let batch = batch(json!({ "items": [{ "slug": "manga" }] }));
+
+        assert!(matches!(
+            batch.validate(),
+            Err(ValidationError::BatchItem { index: 0, source })
+                if matches!(*source, ValidationError::Required("callback_url"))
+        ));
+    }
+}
@@ -1,20 +1,58 @@
-use crate::processing::{ScrapMangaRequest, process};
+use crate::callback::outbox::Outbox;
+use crate::callback::policy::CallbackPolicy;
+use crate::jobs::cancellation::{self, Reason};
+use crate::jobs::{self, Accepted, JobStore, JobTracker};
+use crate::metrics;
+use crate::processing::batch::{self, ScrapMangaBatchRequest};
+use crate::processing::validation::ValidationError;
+use crate::processing::{self, ScrapMangaRequest, process};
+use crate::server::{self, health::Readiness};
+use crate::shutdown::Shutdown;
 use futures::StreamExt;
 use lapin::message::Delivery;
 use lapin::options::{
-    BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions,
+    BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicPublishOptions, BasicQosOptions,
     ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
 };
 use lapin::types::FieldTable;
 use lapin::{
-    Channel, Connection, ConnectionProperties, Consumer, Error as AmqpError, ExchangeKind, Queue,
+    BasicProperties, Channel, Connection, ConnectionProperties, Consumer, Error as AmqpError,
+    ExchangeKind, Queue,
 };
+use serde::Deserialize;
+use serde_json::{Value, json};
 use std::env;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
-use tracing::{error, info};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use uuid::Uuid;
 
 const QUEUE_NAME: &str = "manga_urls_queue";
 const EXCHANGE_NAME: &str = "manga_urls_exchange";
+const CONTROL_EXCHANGE_NAME: &str = "manga_urls_control_exchange";
+
+/// Batch goes first, a single request has no `items`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Message {
+    Batch(ScrapMangaBatchRequest),
+    Single(Box<ScrapMangaRequest>),
+}
+
+/// Published to the fanout control exchange, so every consumer gets it
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum ControlMessage {
+    Cancel {
+        job_id: String,
+        /// Send the failure callback with `cancelled` status
+        #[serde(default)]
+        callback: bool,
+    },
+}
 
 #[derive(Debug, Error)]
 pub enum ConfigErrorType {
@@ -52,6 +90,10 @@ pub enum AmqpWrapperError {
     Ack(AmqpError),
     #[error("Failed to nack {0}")]
     Nack(AmqpError),
+    #[error("Failed to publish {0}")]
+    Publish(AmqpError),
+    #[error("Failed to close AMQP connection {0}")]
+    Close(AmqpError),
 }
 
 #[derive(Debug, Error)]
@@ -62,53 +104,319 @@ pub enum Error {
     Amqp(#[from] AmqpWrapperError),
     #[error("Failed to parse payload {0}")]
     ParseDelivery(#[from] ParseDeliveryErrorType),
+    #[error("Job store error {0}")]
+    Jobs(#[from] jobs::Error),
+    #[error("Processing error {0}")]
+    Processing(#[from] processing::Error),
+    #[error("Invalid payload {0}")]
+    Validation(#[from] ValidationError),
+}
+
+/// What every delivery of the consumer is processed with
+struct Context {
+    channel: Channel,
+    jobs: Arc<dyn JobStore>,
+    callback_policy: CallbackPolicy,
+    /// Parent of job cancellation tokens, cancelled when the grace period is over
+    cancellation: CancellationToken,
+    browsers: Arc<Semaphore>,
+    max_deadline: Duration,
 }
 
-pub async fn consume(url: &str, chrome_max_count: u16) -> Result<(), Error> {
-    let channel = create_channel(url).await?;
+pub async fn consume(
+    url: &str,
+    chrome_max_count: u16,
+    health_port: Option<u16>,
+    grace_period: Duration,
+    max_deadline: Duration,
+) -> Result<(), Error> {
+    metrics::init();
+    let shutdown = Shutdown::new(grace_period);
+    let browsers = Arc::new(Semaphore::new(chrome_max_count as usize));
+    let jobs = jobs::from_env()?;
+    let connection = create_connection(url).await?;
+    let channel = create_channel(&connection).await?;
     create_queue(&channel).await?;
     create_exchange(&channel).await?;
     queue_bind(&channel).await?;
     set_prefetch(&channel, 1).await?;
 
     let mut consumer = create_consumer(&channel).await?;
+    let control_channel = create_channel(&connection).await?;
+    let control_consumer = create_control_consumer(&control_channel).await?;
+    tokio::spawn(consume_control(control_consumer));
+    Outbox::from_env().spawn_redelivery();
+    if let Some(port) = health_port {
+        spawn_probes(
+            port,
+            Readiness::from_env(
+                Some(connection.status().clone()),
+                browsers.clone(),
+                shutdown.clone(),
+            ),
+        );
+    }
 
+    let context = Context {
+        channel,
+        jobs,
+        callback_policy: CallbackPolicy::from_env(),
+        cancellation: shutdown.abort_token().clone(),
+        browsers,
+        max_deadline,
+    };
+    shutdown.listen();
     info!("Waiting for jobs");
 
-    while let Some(delivery) = consumer.next().await {
+    loop {
+        // Running job is finished before the next delivery is awaited, so it is not interrupted here
+        let delivery = tokio::select! {
+            delivery = consumer.next() => delivery,
+            () = shutdown.stopping() => None,
+        };
+        let Some(delivery) = delivery else {
+            break;
+        };
         let Ok(delivery) = delivery else {
             continue;
         };
 
-        let payload = parse_delivery(&delivery);
-
-        let processing_result = match payload {
-            Ok(value) => process(chrome_max_count, value).await,
+        let message = match parse_delivery(&delivery) {
+            Ok(message) => message,
             Err(err) => {
+                // Invalid message never parses, so it is rejected rather than redelivered
                 error!("Parse delivery error: {err:?}");
+                nack(&delivery, false).await?;
+                metrics::AMQP_MESSAGES.with_label_values(&["nack"]).inc();
                 continue;
             }
         };
+        let result = tokio::select! {
+            result = process_delivery(&context, &delivery, message) => result,
+            () = shutdown.expired() => {
+                // Stalled Chrome call or callback, another consumer redoes the job
+                nack(&delivery, true).await?;
+                metrics::AMQP_MESSAGES.with_label_values(&["requeue"]).inc();
+                error!("Job did not stop in time, message is requeued");
+                break;
+            }
+        };
+        settle(&delivery, result).await?;
+    }
+
+    // Prefetched deliveries which were not acked are requeued by the broker
+    info!("Stopped consuming, closing AMQP connection");
+    close(&connection, [&control_channel, &context.channel]).await
+}
+
+fn spawn_probes(port: u16, readiness: Readiness) {
+    tokio::spawn(async move {
+        if let Err(err) = server::serve_probes(port, readiness).await {
+            error!("Health and metrics server error: {err:?}");
+        }
+    });
+}
+
+/// Acks or nacks the delivery by the result of its processing
+async fn settle(delivery: &Delivery, result: Result<(), Error>) -> Result<(), Error> {
+    match result {
+        Ok(()) => {
+            delivery
+                .ack(BasicAckOptions::default())
+                .await
+                .map_err(|err| Error::Amqp(AmqpWrapperError::Ack(err)))?;
+            metrics::AMQP_MESSAGES.with_label_values(&["ack"]).inc();
+        }
+        Err(Error::Processing(processing::Error::CancelRequested)) => {
+            // Cancelled on purpose, so it is neither redone nor dead-lettered
+            delivery
+                .ack(BasicAckOptions::default())
+                .await
+                .map_err(|err| Error::Amqp(AmqpWrapperError::Ack(err)))?;
+            metrics::AMQP_MESSAGES
+                .with_label_values(&["cancelled"])
+                .inc();
+            info!("Job was cancelled on request, message is acked");
+        }
+        Err(Error::Processing(err @ processing::Error::TimedOut(_))) => {
+            // Requeued message would most likely stall again
+            nack(delivery, false).await?;
+            metrics::AMQP_MESSAGES
+                .with_label_values(&["timed_out"])
+                .inc();
+            error!("Job timed out, message is nacked: {err}");
+        }
+        Err(Error::Processing(processing::Error::Cancelled)) => {
+            // Another consumer picks the message up, the job is redone from scratch
+            nack(delivery, true).await?;
+            metrics::AMQP_MESSAGES.with_label_values(&["requeue"]).inc();
+            warn!("Job was cancelled on shutdown, message is requeued");
+        }
+        Err(err) => {
+            nack(delivery, false).await?;
+            metrics::AMQP_MESSAGES.with_label_values(&["nack"]).inc();
+            error!("Processing error: {err:?}");
+        }
+    }
+
+    Ok(())
+}
+
+async fn nack(delivery: &Delivery, requeue: bool) -> Result<(), Error> {
+    delivery
+        .nack(BasicNackOptions {
+            requeue,
+            ..Default::default()
+        })
+        .await
+        .map_err(|err| Error::Amqp(AmqpWrapperError::Nack(err)))
+}
+
+async fn close(connection: &Connection, channels: [&Channel; 2]) -> Result<(), Error> {
+    for channel in channels {
+        channel
+            .close(200, "Shutdown")
+            .await
+            .map_err(AmqpWrapperError::Close)?;
+    }
+    connection
+        .close(200, "Shutdown")
+        .await
+        .map_err(AmqpWrapperError::Close)?;
 
-        match processing_result {
-            Ok(()) => {
-                delivery
-                    .ack(BasicAckOptions::default())
-                    .await
-                    .map_err(|err| Error::Amqp(AmqpWrapperError::Ack(err)))?;
+    Ok(())
+}
+
+async fn process_delivery(
+    context: &Context,
+    delivery: &Delivery,
+    message: Message,
+) -> Result<(), Error> {
+    let payload = match message {
+        Message::Single(payload) => *payload,
+        Message::Batch(batch) => return process_batch_delivery(context, delivery, batch).await,
+    };
+
+    payload.validate(true)?;
+    payload.check_callbacks(&context.callback_policy).await?;
+    let accepted = JobTracker::accept(
+        &context.jobs,
+        payload.slug(),
+        &payload,
+        payload.idempotency_key(),
+        &context.cancellation,
+    )?;
+    let job = match accepted {
+        Accepted::New(job) => job,
+        Accepted::Duplicate(job) => {
+            // Earlier delivery with the key has its own job and callbacks
+            info!(
+                job_id = job.id,
+                "Job was already accepted for idempotency key"
+            );
+            let body = json!({ "job_id": job.id });
+            if let Err(err) = reply(&context.channel, delivery, &body).await {
+                error!(job_id = job.id, "Failed to reply with job id: {err:?}");
             }
-            Err(err) => {
-                delivery
-                    .nack(BasicNackOptions {
-                        requeue: false,
-                        ..Default::default()
-                    })
-                    .await
-                    .map_err(|err| Error::Amqp(AmqpWrapperError::Nack(err)))?;
-                error!("Processing error: {err:?}");
+            return Ok(());
+        }
+    };
+    info!(job_id = job.id(), "Job created");
+    let body = json!({ "job_id": job.id() });
+    if let Err(err) = reply(&context.channel, delivery, &body).await {
+        error!(job_id = job.id(), "Failed to reply with job id: {err:?}");
+    }
+
+    Ok(process(&context.browsers, payload, &job, context.max_deadline).await?)
+}
+
+/// Cancelled batch is requeued whole, including already scrapped slugs
+async fn process_batch_delivery(
+    context: &Context,
+    delivery: &Delivery,
+    batch: ScrapMangaBatchRequest,
+) -> Result<(), Error> {
+    batch.validate()?;
+    batch.check_callbacks(&context.callback_policy).await?;
+    let batch_jobs = batch
+        .requests()
+        .iter()
+        .map(|request| {
+            JobTracker::create(
+                &context.jobs,
+                request.slug(),
+                request,
+                &context.cancellation,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let batch_id = Uuid::new_v4().to_string();
+    let job_ids = batch_jobs.iter().map(JobTracker::id).collect::<Vec<_>>();
+    info!(batch_id = batch_id, job_ids = ?job_ids, "Batch created");
+    let body = json!({ "batch_id": batch_id, "job_ids": job_ids });
+    if let Err(err) = reply(&context.channel, delivery, &body).await {
+        error!(
+            batch_id = batch_id,
+            "Failed to reply with batch id: {err:?}"
+        );
+    }
+
+    batch::process_batch(
+        &context.browsers,
+        &batch_id,
+        batch,
+        batch_jobs,
+        context.max_deadline,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Control messages are not acked, a consumer which is down has no jobs to control
+async fn consume_control(mut consumer: Consumer) {
+    while let Some(delivery) = consumer.next().await {
+        let Ok(delivery) = delivery else {
+            continue;
+        };
+        let message = parse_delivery_data(&delivery.data)
+            .and_then(|data| parse_json::<ControlMessage>(&data));
+        match message {
+            Ok(ControlMessage::Cancel { job_id, callback }) => {
+                if cancellation::cancel(&job_id, Reason::Requested { callback }) {
+                    info!(job_id = job_id, "Job cancellation requested");
+                }
             }
+            Err(err) => error!("Parse control message error: {err:?}"),
         }
     }
+}
+
+/// Replies to `reply_to` queue of the delivery if it is set
+async fn reply(
+    channel: &Channel,
+    delivery: &Delivery,
+    body: &Value,
+) -> Result<(), AmqpWrapperError> {
+    let Some(reply_to) = delivery.properties.reply_to() else {
+        return Ok(());
+    };
+
+    let mut properties = BasicProperties::default().with_content_type("application/json".into());
+    if let Some(correlation_id) = delivery.properties.correlation_id() {
+        properties = properties.with_correlation_id(correlation_id.clone());
+    }
+
+    channel
+        .basic_publish(
+            "",
+            reply_to.as_str(),
+            BasicPublishOptions::default(),
+            body.to_string().as_bytes(),
+            properties,
+        )
+        .await
+        .map_err(AmqpWrapperError::Publish)?;
 
     Ok(())
 }
@@ -121,19 +429,21 @@ fn parse_json<T: serde::de::DeserializeOwned>(data: &str) -> Result<T, ParseDeli
     Ok(serde_json::from_str::<T>(data)?)
 }
 
-fn parse_delivery(delivery: &Delivery) -> Result<ScrapMangaRequest, ParseDeliveryErrorType> {
+fn parse_delivery(delivery: &Delivery) -> Result<Message, ParseDeliveryErrorType> {
     let string_data = parse_delivery_data(&delivery.data)?;
     info!(string_data = string_data, "Received delivery");
 
     parse_json(&string_data)
 }
 
-async fn create_channel(url: &str) -> Result<Channel, AmqpWrapperError> {
-    let connect = Connection::connect(url, ConnectionProperties::default())
+async fn create_connection(url: &str) -> Result<Connection, AmqpWrapperError> {
+    Connection::connect(url, ConnectionProperties::default())
         .await
-        .map_err(AmqpWrapperError::Connect)?;
+        .map_err(AmqpWrapperError::Connect)
+}
 
-    connect
+async fn create_channel(connection: &Connection) -> Result<Channel, AmqpWrapperError> {
+    connection
         .create_channel()
         .await
         .map_err(AmqpWrapperError::ChannelCreate)
@@ -187,6 +497,53 @@ async fn create_consumer(channel: &Channel) -> Result<Consumer, AmqpWrapperError
         .map_err(AmqpWrapperError::ConsumerCreate)
 }
 
+/// Exclusive queue of this consumer bound to the control exchange
+async fn create_control_consumer(channel: &Channel) -> Result<Consumer, AmqpWrapperError> {
+    channel
+        .exchange_declare(
+            CONTROL_EXCHANGE_NAME,
+            ExchangeKind::Fanout,
+            ExchangeDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+        .map_err(AmqpWrapperError::ExchangeCreate)?;
+    let queue = channel
+        .queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .map_err(AmqpWrapperError::QueueCreate)?;
+    channel
+        .queue_bind(
+            queue.name().as_str(),
+            CONTROL_EXCHANGE_NAME,
+            "",
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+        .map_err(AmqpWrapperError::QueueBind)?;
+    channel
+        .basic_consume(
+            queue.name().as_str(),
+            "",
+            BasicConsumeOptions {
+                no_ack: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .map_err(AmqpWrapperError::ConsumerCreate)
+}
+
 async fn set_prefetch(channel: &Channel, prefetch_count: u16) -> Result<(), AmqpWrapperError> {
     channel
         .basic_qos(prefetch_count, BasicQosOptions::default())
@@ -1,26 +1,81 @@
+pub mod auth;
+mod catalog;
+mod events;
+pub mod health;
+mod image;
+mod openapi;
+mod queue;
+
+use crate::callback::outbox::Outbox;
+use crate::callback::policy::CallbackPolicy;
+use crate::jobs::cancellation::{self, Reason};
+use crate::jobs::{self, Accepted, Job, JobFilter, JobStatus, JobStore, JobTracker};
+use crate::metrics::{self, METRICS_ROUTE};
 use crate::processing;
 use crate::processing::ScrapMangaRequest;
-use axum::extract::{OriginalUri, State};
+use crate::processing::batch::{self, ScrapMangaBatchRequest};
+use crate::shutdown::Shutdown;
+use auth::ApiKeys;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{OriginalUri, Path, Query, State};
 use axum::http::StatusCode;
-use axum::routing::post;
-use axum::{Json, Router};
+use axum::http::header::RETRY_AFTER;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router, middleware};
+use health::Readiness;
+use image::{IMAGE_ROUTE, ImageProxy};
+use openapi::{CALLBACK_SCHEMA_ROUTE, DOCS_ROUTE, OPENAPI_ROUTE};
+use queue::JobQueue;
+use serde::Deserialize;
 use serde_json::{Value, json};
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::net::TcpListener;
-use tracing::{error, info};
+use tokio::sync::{Semaphore, oneshot};
+use tracing::{error, info, warn};
+use utoipa::IntoParams;
+use uuid::Uuid;
 
 const SCRAP_MANGA_ROUTE: &str = "/scrap-manga";
+const SCRAP_MANGA_SYNC_ROUTE: &str = "/scrap-manga/sync";
+const SCRAP_MANGA_BATCH_ROUTE: &str = "/scrap-manga/batch";
+const JOBS_ROUTE: &str = "/jobs";
+const SEARCH_ROUTE: &str = "/search";
+const MANGA_CHAPTERS_ROUTE: &str = "/manga/{slug}/chapters";
+const QUEUE_RETRY_AFTER_SECONDS: u64 = 30;
 
 #[derive(Clone)]
 struct AppState {
     config: AppConfig,
+    jobs: Arc<dyn JobStore>,
+    callback_policy: CallbackPolicy,
+    shutdown: Shutdown,
+    queue: JobQueue,
+    /// Chrome permits shared by all jobs and catalog requests
+    browsers: Arc<Semaphore>,
 }
 
 impl AppState {
-    pub const fn new(config: AppConfig) -> Self {
-        Self { config }
+    pub fn new(
+        config: AppConfig,
+        jobs: Arc<dyn JobStore>,
+        callback_policy: CallbackPolicy,
+        shutdown: Shutdown,
+    ) -> Self {
+        let queue = JobQueue::start(config.queue_size, config.workers_count, &shutdown);
+        let browsers = Arc::new(Semaphore::new(config.chrome_max_count as usize));
+
+        Self {
+            config,
+            jobs,
+            callback_policy,
+            shutdown,
+            queue,
+            browsers,
+        }
     }
 }
 
@@ -28,6 +83,10 @@ impl AppState {
 struct AppConfig {
     port: u16,
     chrome_max_count: u16,
+    sync_timeout: Duration,
+    queue_size: usize,
+    workers_count: usize,
+    max_deadline: Duration,
 }
 
 impl AppConfig {
@@ -35,14 +94,36 @@ impl AppConfig {
     pub fn from_env() -> Result<Self, ConfigErrorType> {
         let port = env::var("APP_PORT")?.parse::<u16>()?;
         let chrome_max_count = env::var("CHROME_MAX_COUNT")?.parse::<u16>()?;
+        let sync_timeout = Duration::from_secs(env::var("SYNC_TIMEOUT")?.parse::<u64>()?);
+        let queue_size = env::var("QUEUE_SIZE")?.parse::<usize>()?;
+        let workers_count = env::var("WORKERS_COUNT")?.parse::<usize>()?;
+        let max_deadline = Duration::from_secs(env::var("MAX_DEADLINE")?.parse::<u64>()?);
 
-        Ok(Self::new(port, chrome_max_count))
+        Ok(Self::new(
+            port,
+            chrome_max_count,
+            sync_timeout,
+            queue_size,
+            workers_count,
+            max_deadline,
+        ))
     }
 
-    pub const fn new(port: u16, chrome_max_count: u16) -> Self {
+    pub const fn new(
+        port: u16,
+        chrome_max_count: u16,
+        sync_timeout: Duration,
+        queue_size: usize,
+        workers_count: usize,
+        max_deadline: Duration,
+    ) -> Self {
         Self {
             port,
             chrome_max_count,
+            sync_timeout,
+            queue_size,
+            workers_count,
+            max_deadline,
         }
     }
 
@@ -64,42 +145,613 @@ pub enum Error {
     #[error("Error while parsing config {0}")]
     Config(#[from] ConfigErrorType),
     #[error("Server error {0}")]
-    ServerError(#[from] std::io::Error),
+    Server(#[from] std::io::Error),
+    #[error("Job store error {0}")]
+    Jobs(#[from] jobs::Error),
+    #[error("API keys error {0}")]
+    Auth(#[from] auth::Error),
+    #[error("Image proxy error {0}")]
+    Image(#[from] image::Error),
 }
 
-pub async fn serve(port: u16, chrome_max_count: u16) -> Result<(), Error> {
-    let config = AppConfig::new(port, chrome_max_count);
-    let state = Arc::new(AppState::new(config));
+pub async fn serve(
+    port: u16,
+    chrome_max_count: u16,
+    sync_timeout: Duration,
+    grace_period: Duration,
+    queue_size: usize,
+    workers_count: usize,
+    max_deadline: Duration,
+) -> Result<(), Error> {
+    metrics::init();
+    let config = AppConfig::new(
+        port,
+        chrome_max_count,
+        sync_timeout,
+        queue_size,
+        workers_count,
+        max_deadline,
+    );
+    let shutdown = Shutdown::new(grace_period);
+    let state = Arc::new(AppState::new(
+        config,
+        jobs::from_env()?,
+        CallbackPolicy::from_env(),
+        shutdown.clone(),
+    ));
     let address = state.config.address();
+    let readiness = Readiness::from_env(None, state.browsers.clone(), shutdown.clone());
     let listener = TcpListener::bind(&address).await?;
+    Outbox::from_env().spawn_redelivery();
 
-    let router: Router = Router::new()
+    let mut router = Router::new()
         .route(SCRAP_MANGA_ROUTE, post(scrap_manga))
         .route(&format!("{SCRAP_MANGA_ROUTE}/"), post(scrap_manga))
+        .route(SCRAP_MANGA_SYNC_ROUTE, post(scrap_manga_sync))
+        .route(SCRAP_MANGA_BATCH_ROUTE, post(scrap_manga_batch))
+        .route(JOBS_ROUTE, get(list_jobs))
+        .route(
+            &format!("{JOBS_ROUTE}/{{id}}"),
+            get(get_job).delete(cancel_job),
+        )
+        .route(&format!("{JOBS_ROUTE}/{{id}}/result"), get(get_job_result))
+        .route(&format!("{JOBS_ROUTE}/{{id}}/events"), get(events::sse))
+        .route(
+            &format!("{JOBS_ROUTE}/{{id}}/events/ws"),
+            get(events::websocket),
+        )
+        .route(SEARCH_ROUTE, get(catalog::search))
+        .route(MANGA_CHAPTERS_ROUTE, get(catalog::manga_chapters));
+    // Routes added after the layer, like health and metrics, stay open for probes and scrapers
+    if let Some(keys) = ApiKeys::from_env().await? {
+        keys.clone().spawn_reload_on_signal();
+        router = router.route_layer(middleware::from_fn_with_state(keys, auth::authenticate));
+    } else {
+        warn!("API_KEYS and API_KEYS_FILE are not set, server accepts anonymous requests");
+    }
+
+    // `<img>` tags cannot send API keys, the host allowlist keeps the proxy from being an open one
+    let image_proxy = Arc::new(ImageProxy::from_env().await?);
+    let router: Router = router
         .with_state(state)
+        .route(IMAGE_ROUTE, get(image::proxy).with_state(image_proxy))
+        .route(METRICS_ROUTE, get(metrics::handler))
+        .route(OPENAPI_ROUTE, get(openapi::openapi))
+        .route(DOCS_ROUTE, get(openapi::docs))
+        .route(CALLBACK_SCHEMA_ROUTE, get(openapi::callback_schema))
+        .merge(health::router(readiness))
         .fallback(handle_404);
 
+    shutdown.listen();
     info!(address = address, "Web server is up");
+    // Requests are served until running jobs are over, so probes see `/readyz` answering `503` meanwhile
+    let drained = {
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown.stopping().await;
+            shutdown.drain().await;
+        }
+    };
+    axum::serve(listener, router)
+        .with_graceful_shutdown(drained)
+        .await?;
+    info!("Web server is down");
+
+    Ok(())
+}
+
+/// Health and metrics endpoints for the consumer, which has no web server of its own
+pub async fn serve_probes(port: u16, readiness: Readiness) -> Result<(), Error> {
+    let address = format!("0.0.0.0:{port}");
+    let listener = TcpListener::bind(&address).await?;
+    let router = health::router(readiness).route(METRICS_ROUTE, get(metrics::handler));
+
+    info!(address = address, "Health and metrics server is up");
     axum::serve(listener, router).await?;
 
     Ok(())
 }
 
+#[utoipa::path(
+    post,
+    path = "/scrap-manga",
+    tag = "scrapping",
+    request_body = ScrapMangaRequest,
+    responses(
+        (status = 202, description = "Job is accepted, result is sent to `callback_url`", body = openapi::JobAcceptedResponse),
+        (status = 400, description = "Malformed JSON", body = openapi::ErrorResponse),
+        (status = 422, description = "Invalid request", body = openapi::ErrorResponse),
+        (status = 429, description = "Job queue is full, retry after `Retry-After` seconds", body = openapi::ErrorResponse),
+        (status = 503, description = "Server is shutting down", body = openapi::ErrorResponse)
+    )
+)]
 async fn scrap_manga(
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<ScrapMangaRequest>,
-) -> (StatusCode, Json<Value>) {
-    tokio::spawn(async move {
-        if let Err(err) = processing::process(state.config.chrome_max_count, payload).await {
+    payload: Result<Json<ScrapMangaRequest>, JsonRejection>,
+) -> Response {
+    let payload = match validated(payload, true, &state.callback_policy).await {
+        Ok(payload) => payload,
+        Err(response) => return response.into_response(),
+    };
+    let permit = match state.queue.reserve() {
+        Ok(permit) => permit,
+        Err(err) => return queue_unavailable(&err),
+    };
+    let job = match accept_job(&state, &payload) {
+        Ok(Accepted::New(job)) => job,
+        Ok(Accepted::Duplicate(job)) => {
+            return (
+                StatusCode::ACCEPTED,
+                Json(json!({
+                    "success": true,
+                    "message": "Manga scrapping job was already accepted for this idempotency key",
+                    "job_id": job.id
+                })),
+            )
+                .into_response();
+        }
+        Err(response) => return response.into_response(),
+    };
+
+    let job_id = job.id().to_string();
+    let browsers = state.browsers.clone();
+    let max_deadline = state.config.max_deadline;
+    permit.send(Box::pin(async move {
+        if let Err(err) = processing::process(&browsers, payload, &job, max_deadline).await {
             error!("Error while processing manga: {err:?}");
         }
-    });
+    }));
+
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "success": true,
+            "message": "Manga scrapping job accepted",
+            "job_id": job_id
+        })),
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/scrap-manga/sync",
+    tag = "scrapping",
+    request_body = ScrapMangaRequest,
+    responses(
+        (status = 200, description = "Scrapped manga", body = openapi::SyncResultResponse),
+        (status = 422, description = "Invalid request", body = openapi::ErrorResponse),
+        (status = 409, description = "Earlier job with the same idempotency key is not finished", body = openapi::ErrorResponse),
+        (status = 500, description = "Scrapping failed", body = openapi::SyncFailureResponse),
+        (status = 429, description = "Job queue is full, retry after `Retry-After` seconds", body = openapi::ErrorResponse),
+        (status = 503, description = "Server is shutting down", body = openapi::ErrorResponse),
+        (status = 504, description = "Not scrapped in time, the job keeps running", body = openapi::ErrorResponse)
+    )
+)]
+async fn scrap_manga_sync(
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<ScrapMangaRequest>, JsonRejection>,
+) -> Response {
+    let payload = match validated(payload, false, &state.callback_policy).await {
+        Ok(payload) => payload,
+        Err(response) => return response.into_response(),
+    };
+    let permit = match state.queue.reserve() {
+        Ok(permit) => permit,
+        Err(err) => return queue_unavailable(&err),
+    };
+    let job = match accept_job(&state, &payload) {
+        Ok(Accepted::New(job)) => job,
+        Ok(Accepted::Duplicate(job)) => return duplicate_sync_job(*job),
+        Err(response) => return response.into_response(),
+    };
+
+    let job_id = job.id().to_string();
+    // Job keeps running after timeout, its result is available at `/jobs/{id}/result`
+    let browsers = state.browsers.clone();
+    let max_deadline = state.config.max_deadline;
+    let (sender, receiver) = oneshot::channel();
+    permit.send(Box::pin(async move {
+        // Nobody waits for the result after timeout
+        let _ = sender.send(processing::process_sync(&browsers, payload, &job, max_deadline).await);
+    }));
+    match tokio::time::timeout(state.config.sync_timeout, receiver).await {
+        Ok(Ok(Ok(manga))) => (
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "job_id": job_id,
+                "result": manga
+            })),
+        ),
+        Ok(Ok(Err(err))) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "success": false,
+                "message": err.to_string(),
+                "job_id": job_id,
+                "error_kind": err.kind(),
+                "retryable": err.is_retryable()
+            })),
+        ),
+        Ok(Err(_)) => {
+            error!(job_id = job_id, "Sync job panicked");
+            failure(StatusCode::INTERNAL_SERVER_ERROR, "Job failed unexpectedly")
+        }
+        Err(_) => (
+            StatusCode::GATEWAY_TIMEOUT,
+            Json(json!({
+                "success": false,
+                "message": "Manga was not scrapped in time, result will be available later",
+                "job_id": job_id
+            })),
+        ),
+    }
+    .into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/scrap-manga/batch",
+    tag = "scrapping",
+    request_body = ScrapMangaBatchRequest,
+    responses(
+        (status = 202, description = "Batch is accepted, every slug is a job with its own callback, then `BatchCompleted` is sent", body = openapi::BatchAcceptedResponse),
+        (status = 400, description = "Malformed JSON", body = openapi::ErrorResponse),
+        (status = 422, description = "Invalid request", body = openapi::ErrorResponse),
+        (status = 429, description = "Job queue is full, retry after `Retry-After` seconds", body = openapi::ErrorResponse),
+        (status = 503, description = "Server is shutting down", body = openapi::ErrorResponse)
+    )
+)]
+async fn scrap_manga_batch(
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<ScrapMangaBatchRequest>, JsonRejection>,
+) -> Response {
+    let payload = match validated_batch(payload, &state.callback_policy).await {
+        Ok(payload) => payload,
+        Err(response) => return response.into_response(),
+    };
+    // Whole batch takes one place in the queue and one worker
+    let permit = match state.queue.reserve() {
+        Ok(permit) => permit,
+        Err(err) => return queue_unavailable(&err),
+    };
+    let mut jobs = Vec::new();
+    for request in payload.requests() {
+        match create_job(&state, &request) {
+            Ok(job) => jobs.push(job),
+            Err(response) => {
+                for job in jobs {
+                    job.fail("Batch was not accepted");
+                }
+                return response.into_response();
+            }
+        }
+    }
+
+    let batch_id = Uuid::new_v4().to_string();
+    let job_ids = jobs
+        .iter()
+        .map(JobTracker::id)
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    let browsers = state.browsers.clone();
+    let max_deadline = state.config.max_deadline;
+    {
+        let batch_id = batch_id.clone();
+        permit.send(Box::pin(async move {
+            let result =
+                batch::process_batch(&browsers, &batch_id, payload, jobs, max_deadline).await;
+            if let Err(err) = result {
+                error!(batch_id = batch_id, "Error while processing batch: {err:?}");
+            }
+        }));
+    }
 
     (
-        StatusCode::OK,
+        StatusCode::ACCEPTED,
         Json(json!({
             "success": true,
-            "message": "Manga was sent successfully"
+            "message": "Manga scrapping batch accepted",
+            "batch_id": batch_id,
+            "job_ids": job_ids
+        })),
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    tag = "jobs",
+    params(JobFilter),
+    responses(
+        (status = 200, description = "Latest jobs first", body = openapi::JobsResponse),
+        (status = 400, description = "Invalid query", body = openapi::ErrorResponse)
+    )
+)]
+async fn list_jobs(
+    State(state): State<Arc<AppState>>,
+    filter: Result<Query<JobFilter>, QueryRejection>,
+) -> (StatusCode, Json<Value>) {
+    let Query(filter) = match filter {
+        Ok(filter) => filter,
+        Err(rejection) => return failure(rejection.status(), rejection.body_text()),
+    };
+    match state.jobs.list(&filter) {
+        Ok(jobs) => (
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "jobs": jobs
+            })),
+        ),
+        Err(err) => job_store_failure(&err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "Job with its status and progress", body = openapi::JobResponse),
+        (status = 404, description = "Job not found", body = openapi::ErrorResponse)
+    )
+)]
+async fn get_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    match state.jobs.get(&id) {
+        Ok(Some(job)) => (
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "job": job
+            })),
+        ),
+        Ok(None) => job_not_found(&id),
+        Err(err) => job_store_failure(&err),
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CancelJobParams {
+    /// Send the failure callback with `cancelled` status
+    #[serde(default)]
+    callback: bool,
+}
+
+/// Only jobs queued or running in this server can be cancelled
+#[utoipa::path(
+    delete,
+    path = "/jobs/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id"), CancelJobParams),
+    responses(
+        (status = 202, description = "Job is being cancelled, its Chrome tabs are closed", body = openapi::JobAcceptedResponse),
+        (status = 400, description = "Invalid query", body = openapi::ErrorResponse),
+        (status = 404, description = "Job not found", body = openapi::ErrorResponse),
+        (status = 409, description = "Job is finished or runs in another process", body = openapi::ErrorResponse)
+    )
+)]
+async fn cancel_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    params: Result<Query<CancelJobParams>, QueryRejection>,
+) -> (StatusCode, Json<Value>) {
+    let Query(params) = match params {
+        Ok(params) => params,
+        Err(rejection) => return failure(rejection.status(), rejection.body_text()),
+    };
+    let job = match state.jobs.get(&id) {
+        Ok(Some(job)) => job,
+        Ok(None) => return job_not_found(&id),
+        Err(err) => return job_store_failure(&err),
+    };
+    if matches!(job.status, JobStatus::Queued | JobStatus::Running)
+        && cancellation::cancel(
+            &id,
+            Reason::Requested {
+                callback: params.callback,
+            },
+        )
+    {
+        info!(job_id = id, "Job cancellation requested");
+        return (
+            StatusCode::ACCEPTED,
+            Json(json!({
+                "success": true,
+                "message": "Job cancellation requested",
+                "job_id": id
+            })),
+        );
+    }
+
+    match job.status {
+        JobStatus::Queued | JobStatus::Running => failure(
+            StatusCode::CONFLICT,
+            format!("Job {id} is not running in this process"),
+        ),
+        status => failure(
+            StatusCode::CONFLICT,
+            format!("Job {id} is already {}", status.as_str()),
+        ),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/result",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "Final callback payload of the succeeded job", body = openapi::JobResultResponse),
+        (status = 404, description = "Job not found", body = openapi::ErrorResponse),
+        (status = 409, description = "Job failed or is not finished", body = openapi::ErrorResponse)
+    )
+)]
+async fn get_job_result(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let job = match state.jobs.get(&id) {
+        Ok(Some(job)) => job,
+        Ok(None) => return job_not_found(&id),
+        Err(err) => return job_store_failure(&err),
+    };
+
+    match (job.status, job.result) {
+        (JobStatus::Succeeded, Some(result)) => (
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "result": result
+            })),
+        ),
+        (JobStatus::Failed, _) => failure(
+            StatusCode::CONFLICT,
+            format!("Job {id} failed: {}", job.error.unwrap_or_default()),
+        ),
+        (JobStatus::Cancelled, _) => {
+            failure(StatusCode::CONFLICT, format!("Job {id} was cancelled"))
+        }
+        (status, _) => failure(
+            StatusCode::CONFLICT,
+            format!("Job {id} has no result yet, status is {}", status.as_str()),
+        ),
+    }
+}
+
+/// Request with already used idempotency key gets the earlier job instead of a new one
+fn accept_job(
+    state: &AppState,
+    payload: &ScrapMangaRequest,
+) -> Result<Accepted, (StatusCode, Json<Value>)> {
+    JobTracker::accept(
+        &state.jobs,
+        payload.slug(),
+        payload,
+        payload.idempotency_key(),
+        state.shutdown.abort_token(),
+    )
+    .map_err(|err| {
+        error!("Error while creating job: {err:?}");
+        failure(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create job")
+    })
+}
+
+/// Result of the succeeded earlier job, otherwise its id to follow at `/jobs/{id}`
+fn duplicate_sync_job(job: Job) -> Response {
+    match (job.status, job.result) {
+        (JobStatus::Succeeded, Some(result)) => (
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "job_id": job.id,
+                "result": result
+            })),
+        ),
+        (status, _) => (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "success": false,
+                "message": format!(
+                    "Job was already accepted for this idempotency key, status is {}",
+                    status.as_str()
+                ),
+                "job_id": job.id
+            })),
+        ),
+    }
+    .into_response()
+}
+
+/// Running jobs are cancelled with the server
+fn create_job(
+    state: &AppState,
+    payload: &ScrapMangaRequest,
+) -> Result<JobTracker, (StatusCode, Json<Value>)> {
+    JobTracker::create(
+        &state.jobs,
+        payload.slug(),
+        payload,
+        state.shutdown.abort_token(),
+    )
+    .map_err(|err| {
+        error!("Error while creating job: {err:?}");
+        failure(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create job")
+    })
+}
+
+/// Malformed and invalid requests are answered in the same envelope as other errors
+async fn validated(
+    payload: Result<Json<ScrapMangaRequest>, JsonRejection>,
+    require_callback: bool,
+    callback_policy: &CallbackPolicy,
+) -> Result<ScrapMangaRequest, (StatusCode, Json<Value>)> {
+    let Json(payload) =
+        payload.map_err(|rejection| failure(rejection.status(), rejection.body_text()))?;
+    payload
+        .validate(require_callback)
+        .map_err(|err| failure(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()))?;
+    payload
+        .check_callbacks(callback_policy)
+        .await
+        .map_err(|err| failure(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()))?;
+
+    Ok(payload)
+}
+
+fn queue_unavailable(err: &queue::Error) -> Response {
+    let status = match err {
+        queue::Error::Full => StatusCode::TOO_MANY_REQUESTS,
+        queue::Error::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (
+        status,
+        [(RETRY_AFTER, QUEUE_RETRY_AFTER_SECONDS.to_string())],
+        failure(status, err.to_string()).1,
+    )
+        .into_response()
+}
+
+async fn validated_batch(
+    payload: Result<Json<ScrapMangaBatchRequest>, JsonRejection>,
+    callback_policy: &CallbackPolicy,
+) -> Result<ScrapMangaBatchRequest, (StatusCode, Json<Value>)> {
+    let Json(payload) =
+        payload.map_err(|rejection| failure(rejection.status(), rejection.body_text()))?;
+    payload
+        .validate()
+        .map_err(|err| failure(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()))?;
+    payload
+        .check_callbacks(callback_policy)
+        .await
+        .map_err(|err| failure(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()))?;
+
+    Ok(payload)
+}
+
+fn job_not_found(id: &str) -> (StatusCode, Json<Value>) {
+    failure(StatusCode::NOT_FOUND, format!("Job {id} not found"))
+}
+
+fn job_store_failure(err: &jobs::Error) -> (StatusCode, Json<Value>) {
+    error!("Job store error: {err:?}");
+    failure(StatusCode::INTERNAL_SERVER_ERROR, "Job store error")
+}
+
+fn failure<T: Into<String>>(status: StatusCode, message: T) -> (StatusCode, Json<Value>) {
+    (
+        status,
+        Json(json!({
+            "success": false,
+            "message": message.into()
         })),
     )
 }
@@ -0,0 +1,225 @@
+use axum::Json;
+use axum::extract::{Request, State};
+use axum::http::header::{AUTHORIZATION, RETRY_AFTER};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{error, info, warn};
+
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 60;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to read API keys file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Bad API key rate limit on line {0}")]
+    BadRateLimit(usize),
+    #[error("Bad API_KEY_RATE_LIMIT: {0}")]
+    BadDefaultRateLimit(String),
+}
+
+/// Where keys come from, one `key` or `key:requests_per_minute` per line or comma separated
+#[derive(Debug, Clone)]
+enum Source {
+    Env(String),
+    File(PathBuf),
+}
+
+impl Source {
+    async fn read(&self) -> Result<String, Error> {
+        match self {
+            Self::Env(keys) => Ok(keys.replace(',', "\n")),
+            Self::File(path) => Ok(tokio::fs::read_to_string(path).await?),
+        }
+    }
+}
+
+struct Key {
+    quota: Quota,
+    limiter: Arc<DefaultDirectRateLimiter>,
+}
+
+/// API keys indexed by their SHA-256, so lookups do not compare secrets directly
+#[derive(Clone)]
+pub struct ApiKeys {
+    source: Source,
+    default_quota: Quota,
+    keys: Arc<RwLock<HashMap<[u8; 32], Key>>>,
+}
+
+impl ApiKeys {
+    /// `None` when neither `API_KEYS` nor `API_KEYS_FILE` is set, the server is open then
+    pub async fn from_env() -> Result<Option<Self>, Error> {
+        let source = match (env::var("API_KEYS_FILE"), env::var("API_KEYS")) {
+            (Ok(path), keys) if !path.is_empty() => {
+                if keys.is_ok_and(|keys| !keys.is_empty()) {
+                    warn!("Both API_KEYS_FILE and API_KEYS are set, API_KEYS is ignored");
+                }
+                Source::File(path.into())
+            }
+            (_, Ok(keys)) if !keys.is_empty() => Source::Env(keys),
+            _ => return Ok(None),
+        };
+        let rate_limit = match env::var("API_KEY_RATE_LIMIT") {
+            Ok(value) => value
+                .parse::<NonZeroU32>()
+                .map_err(|err| Error::BadDefaultRateLimit(err.to_string()))?,
+            Err(_) => {
+                NonZeroU32::new(DEFAULT_RATE_LIMIT_PER_MINUTE).expect("Bad default rate limit")
+            }
+        };
+
+        let keys = Self {
+            source,
+            default_quota: Quota::per_minute(rate_limit),
+            keys: Arc::default(),
+        };
+        keys.reload().await?;
+
+        Ok(Some(keys))
+    }
+
+    /// Rate limiter state is kept for keys whose limit did not change
+    pub async fn reload(&self) -> Result<usize, Error> {
+        let content = self.source.read().await?;
+        let mut parsed = HashMap::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, quota) = match line.rsplit_once(':') {
+                Some((key, limit)) => (
+                    key.trim(),
+                    limit
+                        .trim()
+                        .parse::<NonZeroU32>()
+                        .map(Quota::per_minute)
+                        .map_err(|_| Error::BadRateLimit(index + 1))?,
+                ),
+                None => (line, self.default_quota),
+            };
+            parsed.insert(hash(key), quota);
+        }
+
+        let mut keys = self.keys.write().expect("API keys lock is poisoned");
+        let mut reloaded = HashMap::with_capacity(parsed.len());
+        for (hash, quota) in parsed {
+            let limiter = match keys.remove(&hash) {
+                Some(key) if key.quota == quota => key.limiter,
+                _ => Arc::new(RateLimiter::direct(quota)),
+            };
+            reloaded.insert(hash, Key { quota, limiter });
+        }
+        *keys = reloaded;
+
+        Ok(keys.len())
+    }
+
+    /// Reloads keys on SIGHUP, so they can be rotated without restart; only a file can change
+    /// while the process runs, keys from `API_KEYS` are not reloaded
+    pub fn spawn_reload_on_signal(self) {
+        if let Source::Env(_) = self.source {
+            info!("API keys are set by API_KEYS, they are not reloaded on SIGHUP");
+            return;
+        }
+
+        tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(err) => {
+                    error!("Failed to listen for SIGHUP, API keys will not be reloaded: {err:?}");
+                    return;
+                }
+            };
+
+            while hangup.recv().await.is_some() {
+                match self.reload().await {
+                    Ok(count) => info!(keys_count = count, "API keys reloaded"),
+                    Err(err) => error!("Failed to reload API keys, old keys are kept: {err:?}"),
+                }
+            }
+        });
+    }
+
+    fn limiter(&self, key: &str) -> Option<Arc<DefaultDirectRateLimiter>> {
+        self.keys
+            .read()
+            .expect("API keys lock is poisoned")
+            .get(&hash(key))
+            .map(|key| key.limiter.clone())
+    }
+}
+
+/// Accepts the key as `Authorization: Bearer <key>`, scheme in any case, or `X-Api-Key: <key>`
+pub async fn authenticate(State(keys): State<ApiKeys>, request: Request, next: Next) -> Response {
+    let Some(key) = extract_key(request.headers()) else {
+        return rejection(StatusCode::UNAUTHORIZED, "API key is required", None);
+    };
+    let Some(limiter) = keys.limiter(key) else {
+        warn!("Request with unknown API key");
+        return rejection(StatusCode::UNAUTHORIZED, "API key is invalid", None);
+    };
+
+    if let Err(not_until) = limiter.check() {
+        let wait = not_until.wait_time_from(DefaultClock::default().now());
+        return rejection(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Rate limit exceeded",
+            Some(wait.as_secs().max(1)),
+        );
+    }
+
+    next.run(request).await
+}
+
+fn extract_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, key)| key)
+        .or_else(|| {
+            headers
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+        })
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+fn rejection(status: StatusCode, message: &str, retry_after: Option<u64>) -> Response {
+    let mut response = (
+        status,
+        Json(json!({
+            "success": false,
+            "message": message
+        })),
+    )
+        .into_response();
+    if let Some(seconds) = retry_after {
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(seconds));
+    }
+
+    response
+}
+
+fn hash(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
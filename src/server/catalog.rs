@@ -0,0 +1,137 @@
+use super::{AppState, failure, openapi};
+use crate::mangalib::search::{self, MangaType, SendingError};
+use crate::mangalib::{Client, HeadlessBrowserClient};
+use crate::processing::selection;
+use crate::processing::validation::validate_slug;
+use axum::Json;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::http::header::RETRY_AFTER;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tracing::error;
+use utoipa::IntoParams;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    /// Title to search for, whole catalog when omitted
+    q: Option<String>,
+    #[serde(rename = "type")]
+    #[param(rename = "type")]
+    r#type: Option<MangaType>,
+    /// Starts from 1
+    page: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/search",
+    tag = "catalog",
+    params(SearchParams),
+    responses(
+        (status = 200, description = "Page of the mangalib catalog", body = openapi::SearchResponse),
+        (status = 400, description = "Invalid query", body = openapi::ErrorResponse),
+        (status = 429, description = "Search rate limit is exceeded, retry after `Retry-After` seconds", body = openapi::ErrorResponse),
+        (status = 502, description = "Mangalib request failed", body = openapi::ErrorResponse)
+    )
+)]
+pub async fn search(params: Result<Query<SearchParams>, QueryRejection>) -> Response {
+    let Query(params) = match params {
+        Ok(params) => params,
+        Err(rejection) => {
+            return failure(rejection.status(), rejection.body_text()).into_response();
+        }
+    };
+    let q = params.q.filter(|q| !q.trim().is_empty());
+    match search::search(
+        q,
+        params.r#type.into_iter().collect(),
+        params.page.unwrap_or(1),
+    )
+    .await
+    {
+        Ok(page) => (
+            StatusCode::OK,
+            Json(json!({
+                "success": true,
+                "result": page
+            })),
+        )
+            .into_response(),
+        Err(SendingError::RateLimited(wait)) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, wait.as_secs().max(1).to_string())],
+            failure(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Search rate limit is exceeded",
+            )
+            .1,
+        )
+            .into_response(),
+        Err(err) => {
+            error!("Search error: {err:?}");
+            failure(StatusCode::BAD_GATEWAY, "Failed to search mangalib").into_response()
+        }
+    }
+}
+
+/// Chapters are sorted like for scrapping, images are not scrapped
+#[utoipa::path(
+    get,
+    path = "/manga/{slug}/chapters",
+    tag = "catalog",
+    params(("slug" = String, Path, description = "Manga slug")),
+    responses(
+        (status = 200, description = "Chapters of the manga", body = openapi::ChaptersResponse),
+        (status = 422, description = "Invalid slug", body = openapi::ErrorResponse),
+        (status = 502, description = "Mangalib request failed", body = openapi::ErrorResponse)
+    )
+)]
+pub async fn manga_chapters(
+    State(state): State<Arc<AppState>>,
+    Path(slug): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    if let Err(err) = validate_slug(&slug) {
+        return failure(StatusCode::UNPROCESSABLE_ENTITY, err.to_string());
+    }
+
+    let Ok(_permit) = state.browsers.acquire().await else {
+        return failure(StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down");
+    };
+    let chapters = {
+        let slug = slug.clone();
+        tokio::task::spawn_blocking(move || {
+            HeadlessBrowserClient::builder()
+                .build()
+                .get_manga_chapters(&slug)
+        })
+        .await
+    };
+
+    match chapters {
+        Ok(Ok(chapters)) => {
+            let (chapters, missing_chapters) = selection::normalize(&slug, chapters);
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "success": true,
+                    "slug": slug,
+                    "chapters": chapters,
+                    "missing_chapters": missing_chapters
+                })),
+            )
+        }
+        Ok(Err(err)) => {
+            error!(manga_slug = slug, "Failed to get chapters: {err:?}");
+            failure(StatusCode::BAD_GATEWAY, err.to_string())
+        }
+        Err(err) => {
+            error!(manga_slug = slug, "Chapters task panicked: {err:?}");
+            failure(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get chapters")
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use super::{AppState, job_not_found, job_store_failure};
+use crate::jobs::Job;
+use crate::jobs::events::{self, JobEvent};
+use async_stream::stream;
+use axum::Json;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use futures::{Stream, StreamExt};
+use serde_json::{Value, json};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+/// Snapshot of the job and its live events, `None` when the job is not running in this process
+type Subscription = (Job, Option<Receiver<JobEvent>>);
+
+/// Streams events of the job as SSE, each event is named by its `type`
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/events",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "`progress` snapshot of the job followed by live events until `succeeded` or `failed`", content_type = "text/event-stream"),
+        (status = 404, description = "Job not found", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn sse(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    let (job, receiver) = match subscribe(&state, &id) {
+        Ok(subscription) => subscription,
+        Err(response) => return response.into_response(),
+    };
+
+    let events = messages(job, receiver).map(|message| {
+        let event = Event::default()
+            .event(message["type"].as_str().unwrap_or_default())
+            .data(message.to_string());
+
+        Ok::<_, Infallible>(event)
+    });
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Same events as `sse`, one JSON text message per event
+pub async fn websocket(
+    upgrade: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response {
+    match subscribe(&state, &id) {
+        Ok((job, receiver)) => upgrade.on_upgrade(move |socket| forward(socket, job, receiver)),
+        Err(response) => response.into_response(),
+    }
+}
+
+async fn forward(mut socket: WebSocket, job: Job, receiver: Option<Receiver<JobEvent>>) {
+    let mut messages = Box::pin(messages(job, receiver));
+    while let Some(message) = messages.next().await {
+        if socket
+            .send(Message::Text(message.to_string().into()))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// Subscribes before reading the job, so no event between them is lost
+fn subscribe(state: &AppState, id: &str) -> Result<Subscription, (StatusCode, Json<Value>)> {
+    let receiver = events::subscribe(id);
+    match state.jobs.get(id) {
+        Ok(Some(job)) => Ok((job, receiver)),
+        Ok(None) => Err(job_not_found(id)),
+        Err(err) => Err(job_store_failure(&err)),
+    }
+}
+
+/// Jobs finished or running in another process have only the `progress` snapshot
+fn messages(job: Job, receiver: Option<Receiver<JobEvent>>) -> impl Stream<Item = Value> {
+    stream! {
+        yield json!({
+            "type": "progress",
+            "status": job.status,
+            "chapters_done": job.chapters_done,
+            "chapters_total": job.chapters_total,
+            "error": job.error
+        });
+
+        let Some(mut receiver) = receiver else {
+            return;
+        };
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let is_final = event.is_final();
+                    yield serde_json::to_value(&event).unwrap_or_default();
+                    if is_final {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(job_id = job.id, skipped = skipped, "Job events subscriber lagged behind");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::mangalib::HeadlessBrowserClient;
+use crate::shutdown::Shutdown;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use lapin::ConnectionStatus;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tracing::warn;
+
+pub const HEALTH_ROUTE: &str = "/healthz";
+pub const READY_ROUTE: &str = "/readyz";
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+const CHROME_CHECK_TTL: Duration = Duration::from_secs(30);
+const CHECK_UPSTREAM_ENV: &str = "READINESS_CHECK_UPSTREAM";
+
+type CheckedAt = (Instant, Result<(), String>);
+
+#[derive(Debug, Serialize)]
+struct Check {
+    name: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<(), String>) -> Self {
+        Self {
+            name,
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+/// What must work before the instance can take scrapping requests
+#[derive(Clone)]
+pub struct Readiness {
+    amqp: Option<ConnectionStatus>,
+    /// Chrome check takes one of the browsers used by jobs, so probes do not launch extra ones
+    browsers: Arc<Semaphore>,
+    chrome: Arc<Mutex<Option<CheckedAt>>>,
+    check_upstream: bool,
+    shutdown: Shutdown,
+}
+
+impl Readiness {
+    /// Mangalib API is checked only when `READINESS_CHECK_UPSTREAM` is `true`
+    pub fn from_env(
+        amqp: Option<ConnectionStatus>,
+        browsers: Arc<Semaphore>,
+        shutdown: Shutdown,
+    ) -> Self {
+        Self {
+            amqp,
+            browsers,
+            chrome: Arc::default(),
+            check_upstream: env::var(CHECK_UPSTREAM_ENV).is_ok_and(|value| value == "true"),
+            shutdown,
+        }
+    }
+
+    /// Stopping instance is not ready whatever else works, so it gets no new requests
+    async fn check(&self) -> Vec<Check> {
+        if self.shutdown.is_stopping() {
+            return vec![Check::new(
+                "shutdown",
+                Err("Instance is shutting down".to_string()),
+            )];
+        }
+
+        let mut checks = vec![Check::new("chrome", self.check_chrome().await)];
+        if let Some(status) = &self.amqp {
+            checks.push(Check::new("amqp", check_amqp(status)));
+        }
+        if self.check_upstream {
+            checks.push(Check::new("mangalib", check_upstream().await));
+        }
+
+        checks
+    }
+
+    /// Result is reused for `CHROME_CHECK_TTL`, concurrent probes wait for the same launch
+    async fn check_chrome(&self) -> Result<(), String> {
+        let mut cached = self.chrome.lock().await;
+        match cached.as_ref() {
+            Some((checked_at, result)) if checked_at.elapsed() < CHROME_CHECK_TTL => {
+                return result.clone();
+            }
+            _ => {}
+        }
+        let Ok(permit) = self.browsers.clone().try_acquire_owned() else {
+            // Every browser is launched by jobs at the moment
+            return Ok(());
+        };
+
+        let result = launch_chrome(permit).await;
+        *cached = Some((Instant::now(), result.clone()));
+
+        result
+    }
+}
+
+pub fn router(readiness: Readiness) -> Router {
+    let readiness = Arc::new(readiness);
+
+    Router::new().route(HEALTH_ROUTE, get(healthz)).route(
+        READY_ROUTE,
+        get(move || async move { readyz(&readiness).await }),
+    )
+}
+
+async fn healthz() -> (StatusCode, Json<Value>) {
+    (StatusCode::OK, Json(json!({ "success": true })))
+}
+
+async fn readyz(readiness: &Readiness) -> (StatusCode, Json<Value>) {
+    let checks = readiness.check().await;
+    let ready = checks.iter().all(|check| check.ok);
+    if !ready {
+        warn!(checks = ?checks, "Instance is not ready");
+    }
+
+    (
+        if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        },
+        Json(json!({
+            "success": ready,
+            "checks": checks
+        })),
+    )
+}
+
+/// Permit is released only when the launch is over, even after the timeout
+async fn launch_chrome(permit: OwnedSemaphorePermit) -> Result<(), String> {
+    let launch = tokio::task::spawn_blocking(move || {
+        let result = HeadlessBrowserClient::get_browser()
+            .map(drop)
+            .map_err(|err| err.to_string());
+        drop(permit);
+
+        result
+    });
+
+    match tokio::time::timeout(CHECK_TIMEOUT, launch).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(err)) => Err(format!("Chrome launch panicked: {err}")),
+        Err(_) => Err("Chrome was not launched in time".to_string()),
+    }
+}
+
+fn check_amqp(status: &ConnectionStatus) -> Result<(), String> {
+    if status.connected() {
+        Ok(())
+    } else {
+        Err(format!("AMQP connection is {:?}", status.state()))
+    }
+}
+
+/// Any response means the API is reachable, it may reject requests without a browser
+async fn check_upstream() -> Result<(), String> {
+    let url = HeadlessBrowserClient::builder()
+        .build()
+        .base_url()
+        .to_string();
+
+    reqwest::Client::new()
+        .get(url)
+        .timeout(CHECK_TIMEOUT)
+        .send()
+        .await
+        .map(drop)
+        .map_err(|err| err.to_string())
+}
@@ -0,0 +1,383 @@
+mod cache;
+
+use super::failure;
+use crate::callback::policy;
+use crate::config;
+use crate::mangalib;
+use axum::Json;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::header::{
+    ACCEPT_RANGES, CACHE_CONTROL, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RANGE, REFERER,
+    USER_AGENT,
+};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use cache::{Image, ImageCache};
+use reqwest::Url;
+use reqwest::redirect::Policy;
+use serde::Deserialize;
+use serde_json::Value;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::error;
+use utoipa::IntoParams;
+
+pub const IMAGE_ROUTE: &str = "/image";
+const DEFAULT_ALLOWED_HOSTS: &str = "imgslib.link,imglib.info,mangalib.me,mangalib.org,cdnlibs.org";
+const MANGALIB_REFERER: &str = "https://mangalib.me/";
+const MAX_IMAGE_SIZE: usize = 20 * 1024 * 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_REDIRECTS: usize = 5;
+const IMAGE_CACHE_CONTROL: &str = "public, max-age=86400";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Image cache IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to build HTTP client: {0}")]
+    Client(#[from] reqwest::Error),
+    #[error("Failed to parse {0}: {1}")]
+    Config(&'static str, String),
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImageParams {
+    /// Image url from `images_urls`
+    url: String,
+}
+
+/// Fetches images of allowed hosts with mangalib referer, so they are not rejected as hotlinked
+pub struct ImageProxy {
+    client: reqwest::Client,
+    allowed_hosts: Vec<String>,
+    cache: ImageCache,
+}
+
+impl ImageProxy {
+    pub async fn from_env() -> Result<Self, Error> {
+        let max_size = match env::var("IMAGE_CACHE_MAX_BYTES") {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|err| Error::Config("IMAGE_CACHE_MAX_BYTES", err.to_string()))?,
+            Err(_) => config::DEFAULT_IMAGE_CACHE_MAX_BYTES,
+        };
+        let directory = env::var("IMAGE_CACHE_DIRECTORY_PATH")
+            .unwrap_or_else(|_| config::DEFAULT_IMAGE_CACHE_DIRECTORY_PATH.to_string());
+        let allowed_hosts = policy::list(
+            &env::var("IMAGE_ALLOWED_HOSTS").unwrap_or_else(|_| DEFAULT_ALLOWED_HOSTS.to_string()),
+        );
+
+        // Every redirect is checked like the requested url, so the proxy cannot be sent elsewhere
+        let redirect_hosts = allowed_hosts.clone();
+        let redirect = Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if is_allowed(&redirect_hosts, attempt.url()) {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        });
+
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .redirect(redirect)
+                .build()?,
+            allowed_hosts,
+            cache: ImageCache::open(directory, max_size).await?,
+        })
+    }
+
+    fn check(&self, url: &str) -> Result<Url, (StatusCode, Json<Value>)> {
+        let url = Url::parse(url).map_err(|err| {
+            failure(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Field url is not a valid url: {err}"),
+            )
+        })?;
+        if !is_allowed(&self.allowed_hosts, &url) {
+            return Err(failure(
+                StatusCode::FORBIDDEN,
+                "Only mangalib image hosts are proxied",
+            ));
+        }
+
+        Ok(url)
+    }
+
+    async fn fetch(&self, url: Url) -> Result<Image, (StatusCode, Json<Value>)> {
+        let bad_gateway = |message: String| {
+            error!(url = url.as_str(), "Failed to fetch image: {message}");
+            failure(StatusCode::BAD_GATEWAY, "Failed to fetch image")
+        };
+
+        let mut response = self
+            .client
+            .get(url.clone())
+            .header(REFERER, MANGALIB_REFERER)
+            .header(USER_AGENT, mangalib::USER_AGENT)
+            .send()
+            .await
+            .map_err(|err| bad_gateway(err.to_string()))?;
+        if !response.status().is_success() {
+            return Err(bad_gateway(format!("status {}", response.status())));
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if !content_type.starts_with("image/") {
+            return Err(bad_gateway(format!("content type {content_type}")));
+        }
+        if response
+            .content_length()
+            .is_some_and(|length| length > MAX_IMAGE_SIZE as u64)
+        {
+            return Err(bad_gateway("image is too large".to_string()));
+        }
+        // Chunked response has no length, so the size is checked while it is read
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| bad_gateway(err.to_string()))?
+        {
+            if bytes.len() + chunk.len() > MAX_IMAGE_SIZE {
+                return Err(bad_gateway("image is too large".to_string()));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(Image::new(bytes, content_type))
+    }
+}
+
+fn is_allowed(allowed_hosts: &[String], url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+        && url.host_str().is_some_and(|host| {
+            let host = host.to_lowercase();
+            allowed_hosts
+                .iter()
+                .any(|allowed| policy::matches_host(&host, allowed))
+        })
+}
+
+/// Image from the disk cache or from mangalib, with `ETag` and single `Range` support
+#[utoipa::path(
+    get,
+    path = "/image",
+    tag = "catalog",
+    params(ImageParams),
+    responses(
+        (status = 200, description = "Image", content_type = "image/*"),
+        (status = 206, description = "Requested range of the image", content_type = "image/*"),
+        (status = 304, description = "Image matches `If-None-Match`"),
+        (status = 403, description = "Host is not an allowed image host", body = super::openapi::ErrorResponse),
+        (status = 416, description = "Range is outside of the image", body = super::openapi::ErrorResponse),
+        (status = 422, description = "Invalid url", body = super::openapi::ErrorResponse),
+        (status = 502, description = "Image request failed", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn proxy(
+    State(proxy): State<Arc<ImageProxy>>,
+    params: Result<Query<ImageParams>, QueryRejection>,
+    headers: HeaderMap,
+) -> Response {
+    let Query(params) = match params {
+        Ok(params) => params,
+        Err(rejection) => {
+            return failure(rejection.status(), rejection.body_text()).into_response();
+        }
+    };
+    let url = match proxy.check(&params.url) {
+        Ok(url) => url,
+        Err(response) => return response.into_response(),
+    };
+
+    let image = if let Some(image) = proxy.cache.get(url.as_str()).await {
+        image
+    } else {
+        let image = match proxy.fetch(url.clone()).await {
+            Ok(image) => image,
+            Err(response) => return response.into_response(),
+        };
+        if let Err(err) = proxy.cache.put(url.as_str(), &image).await {
+            error!(url = url.as_str(), "Failed to cache image: {err:?}");
+        }
+        image
+    };
+
+    respond(image, &headers)
+}
+
+fn respond(image: Image, headers: &HeaderMap) -> Response {
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response_headers.insert(CACHE_CONTROL, HeaderValue::from_static(IMAGE_CACHE_CONTROL));
+    if let Ok(etag) = HeaderValue::from_str(&image.etag) {
+        response_headers.insert(ETAG, etag);
+    }
+
+    let not_modified = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == image.etag)
+        });
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+
+    if let Ok(content_type) = HeaderValue::from_str(&image.content_type) {
+        response_headers.insert(CONTENT_TYPE, content_type);
+    }
+    let length = image.bytes.len();
+    let range = headers
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| parse_range(value, length));
+
+    match range {
+        Some(Range::Satisfiable(start, end)) => {
+            if let Ok(content_range) =
+                HeaderValue::from_str(&format!("bytes {start}-{end}/{length}"))
+            {
+                response_headers.insert(CONTENT_RANGE, content_range);
+            }
+            (
+                StatusCode::PARTIAL_CONTENT,
+                response_headers,
+                image.bytes[start..=end].to_vec(),
+            )
+                .into_response()
+        }
+        Some(Range::Unsatisfiable) => {
+            if let Ok(content_range) = HeaderValue::from_str(&format!("bytes */{length}")) {
+                response_headers.insert(CONTENT_RANGE, content_range);
+            }
+            let (status, body) = failure(
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "Range is outside of the image",
+            );
+            (status, response_headers, body).into_response()
+        }
+        Some(Range::Ignored) | None => {
+            (StatusCode::OK, response_headers, image.bytes).into_response()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Range {
+    /// Inclusive byte positions
+    Satisfiable(usize, usize),
+    Unsatisfiable,
+    /// Multiple ranges and other units are answered with the whole image
+    Ignored,
+}
+
+fn parse_range(value: &str, length: usize) -> Range {
+    let Some(range) = value.trim().strip_prefix("bytes=") else {
+        return Range::Ignored;
+    };
+    if range.contains(',') {
+        return Range::Ignored;
+    }
+    let Some((start, end)) = range.split_once('-') else {
+        return Range::Ignored;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    let bounds = match (start.is_empty(), end.is_empty()) {
+        // Suffix range, the last N bytes, zero of them is unsatisfiable
+        (true, false) => end
+            .parse::<usize>()
+            .ok()
+            .map(|suffix| (length.saturating_sub(suffix), length.saturating_sub(1))),
+        (false, true) => start
+            .parse::<usize>()
+            .ok()
+            .map(|start| (start, length.saturating_sub(1))),
+        (false, false) => match (start.parse::<usize>(), end.parse::<usize>()) {
+            (Ok(start), Ok(end)) if start <= end => {
+                Some((start, end.min(length.saturating_sub(1))))
+            }
+            _ => return Range::Ignored,
+        },
+        (true, true) => return Range::Ignored,
+    };
+
+    match bounds {
+        Some((start, end)) if start < length && start <= end => Range::Satisfiable(start, end),
+        Some(_) => Range::Unsatisfiable,
+        None => Range::Ignored,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse_range("bytes=0-9", 100), Range::Satisfiable(0, 9));
+        assert_eq!(parse_range(" bytes=90- ", 100), Range::Satisfiable(90, 99));
+        assert_eq!(parse_range("bytes=90-200", 100), Range::Satisfiable(90, 99));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse_range("bytes=-10", 100), Range::Satisfiable(90, 99));
+        assert_eq!(parse_range("bytes=-200", 100), Range::Satisfiable(0, 99));
+        assert_eq!(parse_range("bytes=-0", 100), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn ranges_outside_of_image_are_unsatisfiable() {
+        assert_eq!(parse_range("bytes=100-", 100), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=100-200", 100), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn any_range_of_empty_image_is_unsatisfiable() {
+        for value in ["bytes=0-", "bytes=0-0", "bytes=-1"] {
+            assert_eq!(parse_range(value, 0), Range::Unsatisfiable, "{value}");
+        }
+    }
+
+    #[test]
+    fn ignores_unsupported_ranges() {
+        for value in [
+            "bytes=0-1,5-6",
+            "items=0-1",
+            "bytes=5-1",
+            "bytes=-",
+            "bytes=a-b",
+            "bytes=0",
+        ] {
+            assert_eq!(parse_range(value, 100), Range::Ignored, "{value}");
+        }
+    }
+
+    #[test]
+    fn allows_only_http_urls_of_allowed_hosts() {
+        let allowed_hosts = policy::list("imglib.info");
+        let allowed = |url: &str| is_allowed(&allowed_hosts, &Url::parse(url).unwrap());
+
+        assert!(allowed("https://img2.imglib.info/a.png"));
+        assert!(allowed("http://IMGLIB.info/a.png"));
+        assert!(!allowed("ftp://imglib.info/a.png"));
+        assert!(!allowed("https://imglib.info.evil.net/a.png"));
+        assert!(!allowed("https://127.0.0.1/a.png"));
+    }
+}
@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tokio::fs;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub bytes: Vec<u8>,
+    pub content_type: String,
+    pub etag: String,
+}
+
+impl Image {
+    pub fn new(bytes: Vec<u8>, content_type: String) -> Self {
+        let etag = format!("\"{}\"", hex::encode(&Sha256::digest(&bytes)[..16]));
+
+        Self {
+            bytes,
+            content_type,
+            etag,
+        }
+    }
+}
+
+/// Stored next to the image as `{key}.json`, the image itself is `{key}.bin`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Metadata {
+    url: String,
+    content_type: String,
+    etag: String,
+    size: u64,
+}
+
+struct Entry {
+    metadata: Metadata,
+    last_access: u64,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    total_size: u64,
+    clock: u64,
+}
+
+impl Index {
+    fn touch(&mut self, key: &str) -> Option<Metadata> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_access = self.clock;
+
+        Some(entry.metadata.clone())
+    }
+
+    fn insert(&mut self, key: String, metadata: Metadata) {
+        self.clock += 1;
+        self.total_size += metadata.size;
+        let entry = Entry {
+            metadata,
+            last_access: self.clock,
+        };
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.total_size -= previous.metadata.size;
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.total_size -= entry.metadata.size;
+        }
+    }
+
+    /// Least recently used entries to remove until the cache fits into `max_size`
+    fn evict(&mut self, max_size: u64) -> Vec<String> {
+        let mut evicted = vec![];
+        while self.total_size > max_size {
+            let Some(key) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_access)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.remove(&key);
+            evicted.push(key);
+        }
+
+        evicted
+    }
+}
+
+/// Disk cache of proxied images, LRU order is kept in memory and restored from file times on start
+pub struct ImageCache {
+    directory: PathBuf,
+    max_size: u64,
+    index: Mutex<Index>,
+}
+
+impl ImageCache {
+    /// Files of images without complete metadata and image, left by a crash while an image was stored
+    /// or evicted, are removed, so they do not take space beyond `max_size`
+    pub async fn open<T: Into<PathBuf>>(directory: T, max_size: u64) -> std::io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory).await?;
+
+        let mut stored = vec![];
+        let mut files = vec![];
+        let mut dir = fs::read_dir(&directory).await?;
+        while let Some(file) = dir.next_entry().await? {
+            let path = file.path();
+            if path.extension().is_none_or(|extension| extension != "json")
+                || !fs::try_exists(path.with_extension("bin"))
+                    .await
+                    .unwrap_or(false)
+            {
+                files.push(path);
+                continue;
+            }
+            let metadata = fs::read(&path)
+                .await
+                .ok()
+                .and_then(|data| serde_json::from_slice::<Metadata>(&data).ok());
+            let Some(metadata) = metadata else {
+                files.push(path);
+                continue;
+            };
+            let modified = file
+                .metadata()
+                .await
+                .and_then(|file| file.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            stored.push((modified, key(&metadata.url), metadata));
+        }
+        stored.sort_by_key(|(modified, _, _)| *modified);
+
+        let mut index = Index::default();
+        for (_, key, metadata) in stored {
+            index.insert(key, metadata);
+        }
+        for path in files {
+            let is_orphan = file_key(&path).is_some_and(|key| !index.entries.contains_key(key));
+            if is_orphan {
+                warn!(path = ?path, "Removing orphan image cache file");
+                if let Err(err) = fs::remove_file(&path).await {
+                    error!(path = ?path, "Failed to remove orphan image cache file: {err:?}");
+                }
+            }
+        }
+        info!(
+            images_count = index.entries.len(),
+            total_size = index.total_size,
+            "Image cache is loaded"
+        );
+        let cache = Self {
+            directory,
+            max_size,
+            index: Mutex::new(index),
+        };
+        cache.evict().await;
+
+        Ok(cache)
+    }
+
+    pub async fn get(&self, url: &str) -> Option<Image> {
+        let key = key(url);
+        let metadata = self.index.lock().ok()?.touch(&key)?;
+        match fs::read(self.path(&key, "bin")).await {
+            Ok(bytes) => Some(Image {
+                bytes,
+                content_type: metadata.content_type,
+                etag: metadata.etag,
+            }),
+            Err(err) => {
+                error!(url = url, "Failed to read cached image: {err:?}");
+                if let Ok(mut index) = self.index.lock() {
+                    index.remove(&key);
+                }
+                None
+            }
+        }
+    }
+
+    /// Images larger than the whole cache are not stored
+    pub async fn put(&self, url: &str, image: &Image) -> std::io::Result<()> {
+        let size = image.bytes.len() as u64;
+        if size > self.max_size {
+            return Ok(());
+        }
+
+        let key = key(url);
+        let metadata = Metadata {
+            url: url.to_string(),
+            content_type: image.content_type.clone(),
+            etag: image.etag.clone(),
+            size,
+        };
+        self.write(&key, "bin", &image.bytes).await?;
+        self.write(&key, "json", &serde_json::to_vec(&metadata)?)
+            .await?;
+        if let Ok(mut index) = self.index.lock() {
+            index.insert(key, metadata);
+        }
+        self.evict().await;
+
+        Ok(())
+    }
+
+    async fn evict(&self) {
+        let evicted = match self.index.lock() {
+            Ok(mut index) => index.evict(self.max_size),
+            Err(_) => return,
+        };
+        for key in evicted {
+            for extension in ["json", "bin"] {
+                if let Err(err) = fs::remove_file(self.path(&key, extension)).await {
+                    error!(key = key, "Failed to remove cached image: {err:?}");
+                }
+            }
+        }
+    }
+
+    async fn write(&self, key: &str, extension: &str, data: &[u8]) -> std::io::Result<()> {
+        let path = self.path(key, extension);
+        let tmp_path = path.with_extension(format!("{extension}.tmp"));
+        fs::write(&tmp_path, data).await?;
+        fs::rename(tmp_path, path).await
+    }
+
+    fn path(&self, key: &str, extension: &str) -> PathBuf {
+        self.directory.join(format!("{key}.{extension}"))
+    }
+}
+
+fn key(url: &str) -> String {
+    hex::encode(Sha256::digest(url.as_bytes()))
+}
+
+/// Key of `{key}.bin`, `{key}.json` and their `.tmp` files
+fn file_key(path: &Path) -> Option<&str> {
+    path.file_name()?
+        .to_str()?
+        .split_once('.')
+        .map(|(key, _)| key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://imglib.info/a.png";
+
+    #[tokio::test]
+    async fn removes_orphan_files_on_open() {
+        let directory = std::env::temp_dir().join(format!("image-cache-{}", uuid::Uuid::new_v4()));
+        let cache = ImageCache::open(&directory, 1024).await.unwrap();
+        let image = Image::new(vec![1; 10], "image/png".to_string());
+        cache.put(URL, &image).await.unwrap();
+
+        let orphan = key("https://imglib.info/b.png");
+        fs::write(directory.join(format!("{orphan}.bin")), [2; 10])
+            .await
+            .unwrap();
+        fs::write(directory.join(format!("{orphan}.bin.tmp")), [2; 10])
+            .await
+            .unwrap();
+        let without_image = key("https://imglib.info/c.png");
+        fs::write(
+            directory.join(format!("{without_image}.json")),
+            serde_json::to_vec(&Metadata {
+                url: "https://imglib.info/c.png".to_string(),
+                content_type: "image/png".to_string(),
+                etag: String::new(),
+                size: 10,
+            })
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let cache = ImageCache::open(&directory, 1024).await.unwrap();
+        let mut files = vec![];
+        let mut dir = fs::read_dir(&directory).await.unwrap();
+        while let Some(file) = dir.next_entry().await.unwrap() {
+            files.push(file.file_name().into_string().unwrap());
+        }
+        files.sort();
+
+        assert_eq!(
+            files,
+            [format!("{}.bin", key(URL)), format!("{}.json", key(URL))]
+        );
+        assert_eq!(cache.get(URL).await.unwrap().bytes, image.bytes);
+        assert_eq!(cache.index.lock().unwrap().total_size, 10);
+        fs::remove_dir_all(&directory).await.unwrap();
+    }
+}
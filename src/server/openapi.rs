@@ -0,0 +1,203 @@
+//! Response documents here only describe `json!` bodies built by handlers, so they are never constructed
+
+use crate::callback::{Delivery, StreamMessage};
+use crate::jobs::{Job, JobStatus};
+use crate::mangalib::MangaChapter;
+use crate::mangalib::search::SearchPage;
+use crate::processing::batch::{
+    BatchCompleted, BatchItem, BatchItemResult, ScrapMangaBatchRequest,
+};
+use crate::processing::selection::{ChapterFilter, ChapterRef};
+use crate::processing::{
+    FailedChapter, FailedManga, PublishedManga, PublishedMangaChapter, ScrapMangaRequest,
+};
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::Html;
+use serde_json::{Value, json};
+use utoipa::{OpenApi, ToSchema};
+
+pub const OPENAPI_ROUTE: &str = "/openapi.json";
+pub const DOCS_ROUTE: &str = "/docs";
+pub const CALLBACK_SCHEMA_ROUTE: &str = "/schemas/callback.json";
+const CALLBACK_SCHEMAS: [&str; 4] = [
+    "PublishedManga",
+    "StreamMessage",
+    "FailedManga",
+    "BatchCompleted",
+];
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Mangalib parser"),
+    paths(
+        super::scrap_manga,
+        super::scrap_manga_sync,
+        super::scrap_manga_batch,
+        super::list_jobs,
+        super::get_job,
+        super::cancel_job,
+        super::get_job_result,
+        super::events::sse,
+        super::catalog::search,
+        super::catalog::manga_chapters,
+        super::image::proxy
+    ),
+    components(schemas(
+        ScrapMangaRequest,
+        ScrapMangaBatchRequest,
+        BatchItem,
+        BatchCompleted,
+        BatchItemResult,
+        ChapterFilter,
+        ChapterRef,
+        Delivery,
+        PublishedManga,
+        PublishedMangaChapter,
+        FailedChapter,
+        FailedManga,
+        StreamMessage,
+        Job,
+        JobStatus
+    )),
+    tags(
+        (name = "scrapping", description = "Manga scrapping requests"),
+        (name = "jobs", description = "Scrapping jobs and their results"),
+        (name = "catalog", description = "Mangalib catalog without scrapping images")
+    )
+)]
+pub struct ApiDoc;
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct ErrorResponse {
+    success: bool,
+    message: String,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct JobAcceptedResponse {
+    success: bool,
+    message: String,
+    job_id: String,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct BatchAcceptedResponse {
+    success: bool,
+    message: String,
+    batch_id: String,
+    /// Job of every item, in the order of `items`
+    job_ids: Vec<String>,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct SyncResultResponse {
+    success: bool,
+    job_id: String,
+    result: PublishedManga,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct SyncFailureResponse {
+    success: bool,
+    message: String,
+    job_id: String,
+    error_kind: String,
+    retryable: bool,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct JobResponse {
+    success: bool,
+    job: Job,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct JobsResponse {
+    success: bool,
+    jobs: Vec<Job>,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct JobResultResponse {
+    success: bool,
+    result: PublishedManga,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct SearchResponse {
+    success: bool,
+    result: SearchPage,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct ChaptersResponse {
+    success: bool,
+    slug: String,
+    chapters: Vec<MangaChapter>,
+    missing_chapters: Vec<String>,
+}
+
+pub async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+pub async fn docs() -> Html<String> {
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Mangalib parser API</title>
+    <meta charset="utf-8"/>
+</head>
+<body>
+    <redoc spec-url="{OPENAPI_ROUTE}"></redoc>
+    <script src="https://cdn.redoc.ly/redoc/latest/bundles/redoc.standalone.js"></script>
+</body>
+</html>"#
+    ))
+}
+
+/// JSON Schema of any document sent to `callback_url` or `error_callback_url`
+pub async fn callback_schema() -> (StatusCode, Json<Value>) {
+    // OpenAPI 3.1 schemas are JSON Schema, only references to other schemas have to be moved
+    let definitions = ApiDoc::openapi()
+        .to_json()
+        .ok()
+        .map(|openapi| openapi.replace("#/components/schemas/", "#/$defs/"))
+        .and_then(|openapi| serde_json::from_str::<Value>(&openapi).ok())
+        .and_then(|mut openapi| openapi.pointer_mut("/components/schemas").map(Value::take));
+    let Some(definitions) = definitions else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "success": false,
+                "message": "Failed to build callback schema"
+            })),
+        );
+    };
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "Callback payload",
+            // Schemas allow additional properties, so a document may match more than one of them
+            "anyOf": CALLBACK_SCHEMAS
+                .iter()
+                .map(|name| json!({ "$ref": format!("#/$defs/{name}") }))
+                .collect::<Vec<_>>(),
+            "$defs": definitions
+        })),
+    )
+}
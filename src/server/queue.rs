@@ -0,0 +1,74 @@
+use crate::shutdown::Shutdown;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Permit, Receiver, Sender};
+use tracing::error;
+
+pub type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Job queue is full")]
+    Full,
+    #[error("Server is shutting down")]
+    ShuttingDown,
+}
+
+/// Jobs of all requests wait here for one of the workers
+#[derive(Clone)]
+pub struct JobQueue {
+    sender: Sender<Task>,
+    shutdown: Shutdown,
+}
+
+impl JobQueue {
+    pub fn start(size: usize, workers_count: usize, shutdown: &Shutdown) -> Self {
+        let (sender, receiver) = mpsc::channel(size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers_count {
+            shutdown.spawn(work(receiver.clone(), shutdown.clone()));
+        }
+
+        Self {
+            sender,
+            shutdown: shutdown.clone(),
+        }
+    }
+
+    /// Place for a job, taken before the job is created so a full queue leaves no job behind
+    pub fn reserve(&self) -> Result<Permit<'_, Task>, Error> {
+        if self.shutdown.is_stopping() {
+            return Err(Error::ShuttingDown);
+        }
+
+        self.sender.try_reserve().map_err(|err| match err {
+            TrySendError::Full(()) => Error::Full,
+            TrySendError::Closed(()) => Error::ShuttingDown,
+        })
+    }
+}
+
+/// Jobs queued before shutdown are still taken, the grace period applies to them too
+async fn work(receiver: Arc<Mutex<Receiver<Task>>>, shutdown: Shutdown) {
+    loop {
+        let task = {
+            let mut receiver = receiver.lock().await;
+            tokio::select! {
+                task = receiver.recv() => task,
+                () = shutdown.stopping() => receiver.try_recv().ok(),
+            }
+        };
+        let Some(task) = task else {
+            return;
+        };
+
+        // Panicking job must not take the worker down
+        if let Err(err) = tokio::spawn(task).await {
+            error!("Job panicked: {err:?}");
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::future::Future;
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, warn};
+
+/// How long cancelled jobs may take to close their browsers after the grace period
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// SIGTERM or Ctrl+C stops accepting new jobs, running jobs are cancelled after the grace period
+#[derive(Clone)]
+pub struct Shutdown {
+    stopping: CancellationToken,
+    abort: CancellationToken,
+    tasks: TaskTracker,
+    grace_period: Duration,
+}
+
+impl Shutdown {
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            stopping: CancellationToken::new(),
+            abort: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            grace_period,
+        }
+    }
+
+    pub fn listen(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            info!(
+                grace_period = shutdown.grace_period.as_secs(),
+                "Shutting down, waiting for running jobs"
+            );
+            shutdown.stopping.cancel();
+
+            tokio::time::sleep(shutdown.grace_period).await;
+            if !shutdown.tasks.is_empty() {
+                warn!(
+                    jobs_count = shutdown.tasks.len(),
+                    "Grace period is over, cancelling running jobs"
+                );
+            }
+            shutdown.abort.cancel();
+        });
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.is_cancelled()
+    }
+
+    pub fn stopping(&self) -> WaitForCancellationFutureOwned {
+        self.stopping.clone().cancelled_owned()
+    }
+
+    /// Completes when running jobs are given up, the grace period and the time cancelled jobs
+    /// need to stop after the shutdown started
+    pub async fn expired(&self) {
+        self.stopping().await;
+        tokio::time::sleep(self.grace_period + CANCEL_TIMEOUT).await;
+    }
+
+    /// Parent of job cancellation tokens, cancelled when the grace period is over
+    pub const fn abort_token(&self) -> &CancellationToken {
+        &self.abort
+    }
+
+    /// Spawns a job the shutdown waits for
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task);
+    }
+
+    /// Waits for spawned jobs, at most the grace period and the time cancelled jobs need to stop
+    pub async fn drain(&self) {
+        self.tasks.close();
+        let timeout = self.grace_period + CANCEL_TIMEOUT;
+        if tokio::time::timeout(timeout, self.tasks.wait())
+            .await
+            .is_err()
+        {
+            error!(
+                jobs_count = self.tasks.len(),
+                "Jobs did not stop in time, exiting anyway"
+            );
+        }
+    }
+}
+
+async fn wait_for_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(err) => {
+            error!("Failed to listen for SIGTERM: {err:?}");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
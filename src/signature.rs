@@ -0,0 +1,188 @@
+//! HMAC-SHA256 signature of callback requests.
+//!
+//! Signed message is `{timestamp}.{delivery id}.{body}`, the signature header value is `sha256={hex digest}`.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Timestamp";
+pub const DELIVERY_ID_HEADER: &str = "X-Delivery-Id";
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(300);
+
+const SIGNATURE_PREFIX: &str = "sha256=";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Bad timestamp: {0}")]
+    BadTimestamp(String),
+    #[error("Timestamp is outside of tolerance window")]
+    Expired,
+    #[error("Bad signature format")]
+    BadSignatureFormat,
+    #[error("Signature mismatch")]
+    Mismatch,
+}
+
+pub fn sign(secret: &[u8], timestamp: u64, delivery_id: &str, body: &[u8]) -> String {
+    format!(
+        "{SIGNATURE_PREFIX}{}",
+        hex::encode(
+            mac(secret, timestamp, delivery_id, body)
+                .finalize()
+                .into_bytes()
+        )
+    )
+}
+
+/// Checks values of [`SIGNATURE_HEADER`], [`TIMESTAMP_HEADER`] and [`DELIVERY_ID_HEADER`]
+/// against the raw request body
+pub fn verify(
+    secret: &[u8],
+    timestamp: &str,
+    delivery_id: &str,
+    body: &[u8],
+    signature: &str,
+    tolerance: Duration,
+) -> Result<(), Error> {
+    let timestamp = timestamp
+        .trim()
+        .parse::<u64>()
+        .map_err(|err| Error::BadTimestamp(err.to_string()))?;
+    if now().abs_diff(timestamp) > tolerance.as_secs() {
+        return Err(Error::Expired);
+    }
+
+    let signature = signature
+        .trim()
+        .strip_prefix(SIGNATURE_PREFIX)
+        .and_then(|digest| hex::decode(digest).ok())
+        .ok_or(Error::BadSignatureFormat)?;
+
+    mac(secret, timestamp, delivery_id, body)
+        .verify_slice(&signature)
+        .map_err(|_| Error::Mismatch)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn mac(secret: &[u8], timestamp: u64, delivery_id: &str, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(delivery_id.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+    const DELIVERY_ID: &str = "delivery";
+    const BODY: &[u8] = br#"{"a":1}"#;
+
+    fn check(timestamp: u64, delivery_id: &str, signature: &str) -> Result<(), Error> {
+        verify(
+            SECRET,
+            &timestamp.to_string(),
+            delivery_id,
+            BODY,
+            signature,
+            DEFAULT_TOLERANCE,
+        )
+    }
+
+    #[test]
+    fn signs_timestamp_delivery_id_and_body() {
+        assert_eq!(
+            sign(SECRET, 1_700_000_000, DELIVERY_ID, BODY),
+            "sha256=cf7f2f8c92a5c941358bfc512a862984951f1a57acfdc1c00a97ed2f43f923b8"
+        );
+    }
+
+    #[test]
+    fn accepts_valid_signature() {
+        let timestamp = now();
+        let signature = sign(SECRET, timestamp, DELIVERY_ID, BODY);
+
+        assert!(check(timestamp, DELIVERY_ID, &signature).is_ok());
+    }
+
+    #[test]
+    fn rejects_changed_message() {
+        let timestamp = now();
+        let signature = sign(SECRET, timestamp, DELIVERY_ID, BODY);
+
+        assert!(matches!(
+            check(timestamp, "other", &signature),
+            Err(Error::Mismatch)
+        ));
+        assert!(matches!(
+            check(timestamp - 1, DELIVERY_ID, &signature),
+            Err(Error::Mismatch)
+        ));
+        assert!(matches!(
+            verify(
+                b"other",
+                &timestamp.to_string(),
+                DELIVERY_ID,
+                BODY,
+                &signature,
+                DEFAULT_TOLERANCE
+            ),
+            Err(Error::Mismatch)
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_signature_format() {
+        let timestamp = now();
+        let signature = sign(SECRET, timestamp, DELIVERY_ID, BODY);
+        let digest = signature.trim_start_matches(SIGNATURE_PREFIX);
+
+        for signature in [digest, "sha256=not-hex", ""] {
+            assert!(matches!(
+                check(timestamp, DELIVERY_ID, signature),
+                Err(Error::BadSignatureFormat)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_timestamp_outside_of_tolerance() {
+        let timestamp = now() - DEFAULT_TOLERANCE.as_secs() - 1;
+        let signature = sign(SECRET, timestamp, DELIVERY_ID, BODY);
+
+        assert!(matches!(
+            check(timestamp, DELIVERY_ID, &signature),
+            Err(Error::Expired)
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_timestamp() {
+        assert!(matches!(
+            verify(
+                SECRET,
+                "yesterday",
+                DELIVERY_ID,
+                BODY,
+                "sha256=00",
+                DEFAULT_TOLERANCE
+            ),
+            Err(Error::BadTimestamp(_))
+        ));
+    }
+}